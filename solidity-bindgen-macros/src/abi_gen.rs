@@ -1,3 +1,4 @@
+use crate::natspec::{Artifact, Natspec};
 use ethabi::param_type::ParamType;
 use ethabi::{Function, StateMutability};
 use inflector::cases::snakecase::to_snake_case;
@@ -33,12 +34,18 @@ pub fn abi_from_file(path: impl AsRef<Path>) -> TokenStream {
         .to_owned();
     let bytes = std::fs::read(path).unwrap();
 
+    let artifact = Artifact::parse(&bytes);
+
     // See also 4cd1038f-56f2-4cf2-8dbe-672da9006083
-    let abis = ethabi::Contract::load(&bytes[..]).expect("Could not validate ABIs");
-    let abi_str = String::from_utf8(bytes).expect("Abis need to be valid UTF-8");
+    let abi_str = artifact.abi.to_string();
+    let abis = ethabi::Contract::load(abi_str.as_bytes()).expect("Could not validate ABIs");
 
     let struct_name = ident(name);
 
+    let events: Vec<_> = abis.events().collect();
+    let docs = artifact.natspec.contract_docs(&events, &artifact.errors);
+    let docs = doc_attrs(&docs);
+
     let mut send_fns = Vec::new();
     let mut call_fns = Vec::new();
 
@@ -48,15 +55,46 @@ pub fn abi_from_file(path: impl AsRef<Path>) -> TokenStream {
             Method::Send => &mut send_fns,
         };
 
-        let f = fn_from_abi(f);
+        let f = fn_from_abi(f, &artifact.natspec);
         dest.push(f);
     }
 
+    if abis.receive {
+        send_fns.push(quote! {
+            /// Sends ETH to the contract with empty calldata, which is handled
+            /// by the contract's `receive` function.
+            pub async fn receive(&self, value: ::web3::types::U256) -> ::std::result::Result<SolidityBindgenProvider::Out, ::web3::Error> {
+                let mut options = self.solidity_bindgen_send_options(None).unwrap_or_default();
+                options.value = Some(value);
+                self.provider.send_data(::std::vec::Vec::new(), Some(options), None).await
+            }
+        });
+    }
+
+    if abis.fallback {
+        send_fns.push(quote! {
+            /// Sends arbitrary calldata to the contract, which is handled by
+            /// the contract's `fallback` function. Unless the fallback function
+            /// is payable the value must be zero.
+            pub async fn fallback(&self, data: ::std::vec::Vec<u8>, value: ::web3::types::U256) -> ::std::result::Result<SolidityBindgenProvider::Out, ::web3::Error> {
+                let mut options = self.solidity_bindgen_send_options(None).unwrap_or_default();
+                options.value = Some(value);
+                self.provider.send_data(data, Some(options), None).await
+            }
+        });
+    }
+
     quote! {
+        #docs
         // "hygenic" ident for generic
         pub struct #struct_name<SolidityBindgenProvider> {
             provider: ::std::sync::Arc<SolidityBindgenProvider>,
             pub address: ::web3::types::Address,
+            call_options: ::solidity_bindgen::CallOptions,
+            send_options: ::std::option::Option<::solidity_bindgen::SendOptions>,
+            /// The account to send from, if not the context's primary one
+            account: ::std::option::Option<::web3::types::Address>,
+            abi: ::std::sync::Arc<::web3::ethabi::Contract>,
         }
 
         impl<SolidityBindgenProvider> ::std::clone::Clone for #struct_name<SolidityBindgenProvider> {
@@ -64,6 +102,10 @@ pub fn abi_from_file(path: impl AsRef<Path>) -> TokenStream {
                 Self {
                     provider: ::std::clone::Clone::clone(&self.provider),
                     address: self.address,
+                    call_options: ::std::clone::Clone::clone(&self.call_options),
+                    send_options: ::std::clone::Clone::clone(&self.send_options),
+                    account: self.account,
+                    abi: ::std::clone::Clone::clone(&self.abi),
                 }
             }
         }
@@ -73,13 +115,72 @@ pub fn abi_from_file(path: impl AsRef<Path>) -> TokenStream {
                 // Embed ABI into the program
                 let abi = #abi_str;
 
+                // All of the ABIs are verified at compile time, so we can just unwrap here.
+                // Kept as well as given to the provider, for encoding prepared calls.
+                // See also 4cd1038f-56f2-4cf2-8dbe-672da9006083
+                let abi = ::web3::ethabi::Contract::load(abi.as_bytes()).unwrap();
+                let abi = ::std::sync::Arc::new(abi);
+
                 // Set up a wrapper so we can make calls
-                let provider = ::solidity_bindgen::Context::provider(context, address, abi.as_bytes());
+                let provider = ::solidity_bindgen::Context::provider(context, address, ::std::clone::Clone::clone(&abi));
                 let provider = ::std::sync::Arc::new(provider);
                 Self {
                     address,
                     provider,
+                    call_options: ::std::default::Default::default(),
+                    send_options: None,
+                    account: None,
+                    abi,
+                }
+            }
+
+            /// Returns a view of the contract for which all calls read the
+            /// state at the given block rather than the latest block.
+            pub fn at_block(&self, block: impl ::std::convert::Into<::solidity_bindgen::BlockRef>) -> Self {
+                let mut pinned = ::std::clone::Clone::clone(self);
+                pinned.call_options.block = block.into();
+                pinned
+            }
+
+            /// Returns a view of the contract for which all calls use the
+            /// given options, eg: to call as another address. This replaces
+            /// any block set by `at_block`. Calls are still made as the
+            /// account chosen by `with_account` if the options have no `from`.
+            pub fn with_call_options(&self, options: ::solidity_bindgen::CallOptions) -> Self {
+                let mut view = ::std::clone::Clone::clone(self);
+                view.call_options = options;
+                view.call_options.from = view.call_options.from.or(self.account);
+                view
+            }
+
+            /// Returns a view of the contract for which all sends use the
+            /// given options, eg: to set fees or create an access list.
+            /// Options given to `send` are used instead.
+            pub fn with_send_options(&self, options: ::solidity_bindgen::SendOptions) -> Self {
+                let mut view = ::std::clone::Clone::clone(self);
+                view.send_options = Some(options);
+                view
+            }
+
+            /// Returns a view of the contract which sends from, and calls
+            /// as, another account of the context. The context must have a
+            /// signer for it, eg: from `Web3Context::add_signer`.
+            pub fn with_account(&self, account: ::web3::types::Address) -> Self {
+                let mut view = ::std::clone::Clone::clone(self);
+                view.call_options.from = Some(account);
+                view.account = Some(account);
+                view
+            }
+
+            /// The options of a send, which are those of `with_send_options`
+            /// if not given, with the account chosen by `with_account`
+            fn solidity_bindgen_send_options(&self, options: ::std::option::Option<::solidity_bindgen::SendOptions>) -> ::std::option::Option<::solidity_bindgen::SendOptions> {
+                let mut options = options.or_else(|| ::std::clone::Clone::clone(&self.send_options));
+                if let Some(account) = self.account {
+                    let options = options.get_or_insert_with(::std::default::Default::default);
+                    options.from = options.from.or(Some(account));
                 }
+                options
             }
         }
 
@@ -92,9 +193,10 @@ pub fn abi_from_file(path: impl AsRef<Path>) -> TokenStream {
                 &self,
                 func: &'static str,
                 params: impl web3::contract::tokens::Tokenize + Send,
-                options: Option<::web3::contract::Options>,
+                options: Option<::solidity_bindgen::SendOptions>,
                 confirmations: Option<usize>,
             ) -> Result<SolidityBindgenProvider::Out, ::web3::Error> {
+                let options = self.solidity_bindgen_send_options(options);
                 self.provider.send(func, params, options, confirmations).await
             }
 
@@ -143,10 +245,7 @@ fn param_type(kind: &ParamType) -> (TokenStream, usize) {
         ParamType::Tuple(members) => match members.len() {
             0 => (quote! { ::solidity_bindgen::internal::Empty }, 1),
             _ => {
-                let members: Vec<_> = members
-                    .into_iter()
-                    .map(|member| param_type(member))
-                    .collect();
+                let members: Vec<_> = members.iter().map(param_type).collect();
                 // Unwrap is ok because in this branch there must be at least 1 item.
                 let nesting = 1 + members.iter().map(|(_, n)| *n).max().unwrap();
                 let types = members.iter().map(|(ty, _)| ty);
@@ -156,18 +255,33 @@ fn param_type(kind: &ParamType) -> (TokenStream, usize) {
     }
 }
 
+/// Render lines of markdown as doc attributes
+fn doc_attrs(lines: &[String]) -> TokenStream {
+    // Leading space so the output matches what /// would produce
+    let lines = lines.iter().map(|l| format!(" {}", l));
+    quote! { #(#[doc = #lines])* }
+}
+
 pub fn to_rust_name(type_name: &str, eth_name: &str, i: usize) -> String {
-    if eth_name == "" {
+    if eth_name.is_empty() {
         format!("{}_{}", type_name, i)
     } else {
         to_snake_case(eth_name)
     }
 }
 
-pub fn fn_from_abi(function: &Function) -> TokenStream {
+pub fn fn_from_abi(function: &Function, natspec: &Natspec) -> TokenStream {
     let eth_name = &function.name;
     let rust_name = ident(to_rust_name("function", eth_name, 0));
 
+    let rust_params: Vec<_> = function
+        .inputs
+        .iter()
+        .enumerate()
+        .map(|(i, param)| to_rust_name("input", &param.name, i))
+        .collect();
+    let docs = doc_attrs(&natspec.function_docs(function, &rust_params));
+
     // Get the types and names of parameters
     let params_nesting = if function.inputs.len() > 1 { 1 } else { 0 };
     let params_in = function.inputs.iter().enumerate().map(|(i, param)| {
@@ -193,6 +307,7 @@ pub fn fn_from_abi(function: &Function) -> TokenStream {
             }
         }
     });
+    let params_in: Vec<_> = params_in.collect();
 
     let params = function
         .inputs
@@ -248,13 +363,41 @@ pub fn fn_from_abi(function: &Function) -> TokenStream {
     };
 
     let fn_call = match method {
-        Method::Call => quote! { self.provider.call(#eth_name, #params).await },
-        Method::Send => quote! { self.provider.send(#eth_name, #params, None, None).await },
+        Method::Call => {
+            quote! { self.provider.call(#eth_name, #params, ::std::clone::Clone::clone(&self.call_options)).await }
+        }
+        Method::Send => {
+            quote! { self.provider.send(#eth_name, #params, self.solidity_bindgen_send_options(None), None).await }
+        }
+    };
+
+    let prepare = if method == Method::Call {
+        let prepare_name = ident(format!("prepare_{}", rust_name));
+        let prepare_doc = format!(
+            " Prepares a call to `{}` which can be batched with others in a `Multicall`.",
+            rust_name
+        );
+        quote! {
+            #[doc = #prepare_doc]
+            pub fn #prepare_name(&self, #(#params_in),*) -> ::solidity_bindgen::PreparedCall<#ok> {
+                ::solidity_bindgen::PreparedCall::new(
+                    self.address,
+                    &self.abi,
+                    #eth_name,
+                    ::web3::contract::tokens::Tokenize::into_tokens(#params),
+                )
+            }
+        }
+    } else {
+        quote! {}
     };
 
     quote! {
+        #docs
         pub async fn #rust_name(&self, #(#params_in),*) -> ::std::result::Result<#ok, ::web3::Error> {
             #fn_call
         }
+
+        #prepare
     }
 }
@@ -1,4 +1,5 @@
 mod abi_gen;
+mod natspec;
 
 use crate::abi_gen::abi_from_file;
 use std::env::current_dir;
@@ -13,6 +14,10 @@ extern crate quote;
 /// struct will have the same name as the file, and have individual async
 /// methods for each contract function with parameters and output corresponding
 /// to the ABI.
+///
+/// The file may be either a bare ABI or a build artifact with an `abi` field.
+/// If the artifact has NatSpec `devdoc` and `userdoc` fields these are used to
+/// generate documentation.
 #[proc_macro]
 pub fn contract_abi(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
     let s = parse_macro_input!(input as LitStr);
@@ -0,0 +1,283 @@
+use ethabi::param_type::ParamType;
+use ethabi::{Event, Function, Param};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use tiny_keccak::{Hasher as _, Keccak};
+
+/// The parts of a build artifact (eg: from Truffle, or solc --combined-json)
+/// that are used for code generation. A bare ABI file is also accepted, in
+/// which case there is no documentation.
+pub struct Artifact {
+    /// The ABI with any custom errors removed, since ethabi doesn't know how
+    /// to parse those.
+    pub abi: Value,
+    pub errors: Vec<AbiError>,
+    pub natspec: Natspec,
+}
+
+/// A custom error declared in the ABI, eg: `error Unauthorized(address)`
+#[derive(Deserialize)]
+pub struct AbiError {
+    pub name: String,
+    #[serde(default)]
+    pub inputs: Vec<Param>,
+}
+
+impl AbiError {
+    pub fn signature(&self) -> String {
+        signature(&self.name, self.inputs.iter().map(|p| &p.kind))
+    }
+}
+
+impl Artifact {
+    pub fn parse(bytes: &[u8]) -> Self {
+        let json: Value = serde_json::from_slice(bytes).expect("ABIs need to be valid JSON");
+        let (abi, devdoc, userdoc) = match json {
+            Value::Object(mut artifact) => (
+                artifact
+                    .remove("abi")
+                    .expect("Expected an ABI array or an artifact with an abi field"),
+                artifact.remove("devdoc"),
+                artifact.remove("userdoc"),
+            ),
+            abi => (abi, None, None),
+        };
+
+        let (errors, abi): (Vec<_>, Vec<_>) = match abi {
+            Value::Array(entries) => entries
+                .into_iter()
+                .partition(|entry| entry.get("type") == Some(&Value::from("error"))),
+            _ => panic!("Expected the ABI to be an array"),
+        };
+        let errors = errors
+            .into_iter()
+            .map(|e| serde_json::from_value(e).expect("Could not validate ABI errors"))
+            .collect();
+
+        // Documentation is a nicety. Rather than fail to build over a
+        // format change in the compiler output just leave it out.
+        let natspec = Natspec {
+            dev: devdoc
+                .and_then(|d| serde_json::from_value(d).ok())
+                .unwrap_or_default(),
+            user: userdoc
+                .and_then(|d| serde_json::from_value(d).ok())
+                .unwrap_or_default(),
+        };
+
+        Self {
+            abi: Value::Array(abi),
+            errors,
+            natspec,
+        }
+    }
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct DevDoc {
+    title: Option<String>,
+    author: Option<String>,
+    details: Option<String>,
+    methods: HashMap<String, DevItem>,
+    events: HashMap<String, DevItem>,
+    errors: HashMap<String, Vec<DevItem>>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct DevItem {
+    details: Option<String>,
+    params: HashMap<String, String>,
+    returns: HashMap<String, String>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct UserDoc {
+    notice: Option<String>,
+    methods: HashMap<String, UserItem>,
+    events: HashMap<String, UserItem>,
+    errors: HashMap<String, Vec<UserItem>>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct UserItem {
+    notice: Option<String>,
+}
+
+/// The devdoc and userdoc output of solc, rendered as lines of rustdoc.
+#[derive(Default)]
+pub struct Natspec {
+    dev: DevDoc,
+    user: UserDoc,
+}
+
+/// The canonical signature used both for selectors and as keys in the NatSpec
+/// output. eg: `transfer(address,uint256)`
+pub fn signature<'a>(name: &str, kinds: impl Iterator<Item = &'a ParamType>) -> String {
+    let kinds: Vec<_> = kinds.map(|k| k.to_string()).collect();
+    format!("{}({})", name, kinds.join(","))
+}
+
+/// The first 4 bytes of the hash of the signature, which identify functions
+/// and errors.
+fn selector(signature: &str) -> [u8; 4] {
+    let mut hasher = Keccak::v256();
+    hasher.update(signature.as_bytes());
+    let mut hash = [0u8; 32];
+    hasher.finalize(&mut hash);
+    [hash[0], hash[1], hash[2], hash[3]]
+}
+
+fn hex(bytes: &[u8]) -> String {
+    let digits: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    format!("0x{}", digits)
+}
+
+/// Appends a paragraph, separated from the last by a blank line. Multi-line
+/// NatSpec comments are kept as multiple lines.
+fn paragraph(lines: &mut Vec<String>, text: &str) {
+    if !lines.is_empty() {
+        lines.push(String::new());
+    }
+    lines.extend(text.lines().map(|l| l.trim().to_owned()));
+}
+
+impl Natspec {
+    pub fn contract_docs(&self, events: &[&Event], errors: &[AbiError]) -> Vec<String> {
+        let mut lines = Vec::new();
+        if let Some(title) = &self.dev.title {
+            paragraph(&mut lines, title);
+        }
+        if let Some(notice) = &self.user.notice {
+            paragraph(&mut lines, notice);
+        }
+        if let Some(details) = &self.dev.details {
+            paragraph(&mut lines, details);
+        }
+        if let Some(author) = &self.dev.author {
+            paragraph(&mut lines, &format!("Author: {}", author));
+        }
+
+        if !events.is_empty() {
+            paragraph(&mut lines, "# Events");
+            for event in events {
+                let sig = signature(&event.name, event.inputs.iter().map(|p| &p.kind));
+                lines.push(String::new());
+                lines.push(format!("## `{}`", sig));
+                lines.push(format!("Topic: `{:?}`", event.signature()));
+                let user = self.user.events.get(&sig);
+                let dev = self.dev.events.get(&sig);
+                item_docs(
+                    &mut lines,
+                    user,
+                    dev,
+                    &event.inputs.iter().map(|p| &p.name).collect::<Vec<_>>(),
+                );
+            }
+        }
+
+        if !errors.is_empty() {
+            paragraph(&mut lines, "# Errors");
+            for error in errors {
+                let sig = error.signature();
+                lines.push(String::new());
+                lines.push(format!("## `{}`", sig));
+                lines.push(format!("Selector: `{}`", hex(&selector(&sig))));
+                // Errors may be declared more than once with the same
+                // signature, so solc emits a list. The first is good enough.
+                let user = self.user.errors.get(&sig).and_then(|e| e.first());
+                let dev = self.dev.errors.get(&sig).and_then(|e| e.first());
+                item_docs(
+                    &mut lines,
+                    user,
+                    dev,
+                    &error.inputs.iter().map(|p| &p.name).collect::<Vec<_>>(),
+                );
+            }
+        }
+
+        lines
+    }
+
+    pub fn function_docs(&self, function: &Function, rust_params: &[String]) -> Vec<String> {
+        let sig = signature(&function.name, function.inputs.iter().map(|p| &p.kind));
+
+        let mut lines = Vec::new();
+        let user = self.user.methods.get(&sig);
+        let dev = self.dev.methods.get(&sig);
+        if let Some(notice) = user.and_then(|u| u.notice.as_ref()) {
+            paragraph(&mut lines, notice);
+        }
+        if let Some(dev) = dev {
+            if let Some(details) = &dev.details {
+                paragraph(&mut lines, details);
+            }
+            let params: Vec<_> = function
+                .inputs
+                .iter()
+                .zip(rust_params)
+                .filter_map(|(p, rust_name)| {
+                    dev.params
+                        .get(&p.name)
+                        .map(|doc| format!("* `{}`: {}", rust_name, doc))
+                })
+                .collect();
+            if !params.is_empty() {
+                paragraph(&mut lines, "# Parameters");
+                lines.extend(params);
+            }
+            let returns: Vec<_> = function
+                .outputs
+                .iter()
+                .enumerate()
+                .filter_map(|(i, o)| {
+                    let key = if o.name.is_empty() {
+                        format!("_{}", i)
+                    } else {
+                        o.name.clone()
+                    };
+                    dev.returns
+                        .get(&key)
+                        .map(|doc| format!("* `{}`: {}", key, doc))
+                })
+                .collect();
+            if !returns.is_empty() {
+                paragraph(&mut lines, "# Returns");
+                lines.extend(returns);
+            }
+        }
+
+        paragraph(&mut lines, &format!("Solidity: `{}`", function.signature()));
+        lines.push(format!("Selector: `{}`", hex(&selector(&sig))));
+        lines
+    }
+}
+
+/// Docs for items which don't get their own generated code (events, errors),
+/// and so are listed in the docs for the contract.
+fn item_docs(
+    lines: &mut Vec<String>,
+    user: Option<&UserItem>,
+    dev: Option<&DevItem>,
+    params: &[&String],
+) {
+    if let Some(notice) = user.and_then(|u| u.notice.as_ref()) {
+        paragraph(lines, notice);
+    }
+    if let Some(dev) = dev {
+        if let Some(details) = &dev.details {
+            paragraph(lines, details);
+        }
+        let params: Vec<_> = params
+            .iter()
+            .filter_map(|p| dev.params.get(*p).map(|doc| format!("* `{}`: {}", p, doc)))
+            .collect();
+        if !params.is_empty() {
+            paragraph(lines, &params.join("\n"));
+        }
+    }
+}
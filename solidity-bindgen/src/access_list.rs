@@ -0,0 +1,68 @@
+use crate::BoxTransport;
+use serde::Deserialize;
+use web3::api::{Eth, Namespace as _};
+use web3::helpers;
+use web3::types::{AccessList, BlockNumber, CallRequest, U256};
+use web3::Transport as _;
+
+/// An access list created by the node for a transaction, and whether it
+/// pays for itself. Each address and slot in the list costs gas up front,
+/// and saves gas only when the transaction touches it, so a list does not
+/// always reduce the gas used. Notably, the list includes the slots of the
+/// contract called, whose address costs gas to list but is already warm.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AccessListReport {
+    pub access_list: AccessList,
+    /// The estimated gas without an access list
+    pub gas_without: U256,
+    /// The estimated gas with the access list
+    pub gas_with: U256,
+}
+
+impl AccessListReport {
+    pub fn reduces_gas(&self) -> bool {
+        self.gas_with < self.gas_without
+    }
+
+    /// Asks the node for the access list of a transaction, and estimates
+    /// the gas with and without it.
+    pub(crate) async fn create(
+        eth: &Eth<BoxTransport>,
+        request: CallRequest,
+    ) -> web3::Result<Self> {
+        let created = eth
+            .transport()
+            .execute(
+                "eth_createAccessList",
+                vec![
+                    helpers::serialize(&request),
+                    helpers::serialize(&BlockNumber::Pending),
+                ],
+            )
+            .await?;
+        let created: Created = serde_json::from_value(created)
+            .map_err(|e| web3::Error::Decoder(format!("{:?}", e)))?;
+
+        // Estimates from the node are compared, rather than the gasUsed of
+        // eth_createAccessList, so that both are figured the same way
+        let with = CallRequest {
+            access_list: Some(created.access_list.clone()),
+            ..request.clone()
+        };
+        let (gas_without, gas_with) = futures::try_join!(
+            eth.estimate_gas(request, None),
+            eth.estimate_gas(with, None)
+        )?;
+        Ok(Self {
+            access_list: created.access_list,
+            gas_without,
+            gas_with,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Created {
+    access_list: AccessList,
+}
@@ -0,0 +1,112 @@
+use serde::ser::{Serialize, SerializeStruct as _, Serializer};
+use web3::types::{BlockId, BlockNumber, H256, U64};
+
+/// Identifies the block whose state is read by a call.
+///
+/// This is a superset of web3's BlockId, which is missing the `safe` and
+/// `finalized` tags as well as the `requireCanonical` flag from EIP-1898.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum BlockRef {
+    #[default]
+    Latest,
+    Earliest,
+    Pending,
+    /// The most recent block that is unlikely to be reorged (post-merge only)
+    Safe,
+    /// The most recent block that cannot be reorged (post-merge only)
+    Finalized,
+    Number(U64),
+    /// A block by hash. If `require_canonical` is set the node must fail the
+    /// request if the block is not in the canonical chain, rather than reading
+    /// from an uncle.
+    Hash {
+        hash: H256,
+        require_canonical: bool,
+    },
+}
+
+impl From<u64> for BlockRef {
+    fn from(number: u64) -> Self {
+        BlockRef::Number(number.into())
+    }
+}
+
+impl From<U64> for BlockRef {
+    fn from(number: U64) -> Self {
+        BlockRef::Number(number)
+    }
+}
+
+impl From<H256> for BlockRef {
+    fn from(hash: H256) -> Self {
+        BlockRef::Hash {
+            hash,
+            require_canonical: false,
+        }
+    }
+}
+
+impl From<BlockNumber> for BlockRef {
+    fn from(number: BlockNumber) -> Self {
+        match number {
+            BlockNumber::Latest => BlockRef::Latest,
+            BlockNumber::Earliest => BlockRef::Earliest,
+            BlockNumber::Pending => BlockRef::Pending,
+            BlockNumber::Number(number) => BlockRef::Number(number),
+        }
+    }
+}
+
+impl From<BlockId> for BlockRef {
+    fn from(id: BlockId) -> Self {
+        match id {
+            BlockId::Number(number) => number.into(),
+            BlockId::Hash(hash) => hash.into(),
+        }
+    }
+}
+
+impl Serialize for BlockRef {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            BlockRef::Latest => serializer.serialize_str("latest"),
+            BlockRef::Earliest => serializer.serialize_str("earliest"),
+            BlockRef::Pending => serializer.serialize_str("pending"),
+            BlockRef::Safe => serializer.serialize_str("safe"),
+            BlockRef::Finalized => serializer.serialize_str("finalized"),
+            BlockRef::Number(number) => number.serialize(serializer),
+            BlockRef::Hash {
+                hash,
+                require_canonical,
+            } => {
+                let mut s = serializer.serialize_struct("BlockHash", 2)?;
+                s.serialize_field("blockHash", hash)?;
+                s.serialize_field("requireCanonical", require_canonical)?;
+                s.end()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    pub fn serializes_as_json_rpc_block_param() {
+        let ser = |b: BlockRef| serde_json::to_value(b).unwrap();
+        assert_eq!(ser(BlockRef::Finalized), json!("finalized"));
+        assert_eq!(ser(BlockRef::from(255u64)), json!("0xff"));
+        assert_eq!(
+            ser(BlockRef::Hash {
+                hash: H256::repeat_byte(1),
+                require_canonical: true
+            }),
+            json!({
+                "blockHash": "0x0101010101010101010101010101010101010101010101010101010101010101",
+                "requireCanonical": true,
+            })
+        );
+    }
+}
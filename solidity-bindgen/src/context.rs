@@ -1,64 +1,255 @@
-use crate::SafeSecretKey;
+use crate::nonce::NonceManager;
 use crate::Web3Provider;
+use crate::{BoxTransport, FeeModel, Layer, Layered, ReconnectingWebSocket};
+use crate::{SafeSecretKey, Signer};
 use secp256k1::key::SecretKey;
+use std::collections::BTreeMap;
 use std::convert::TryInto as _;
+#[cfg(unix)]
+use std::path::Path;
 use std::sync::Arc;
-use web3::api::Eth;
+use web3::api::{Eth, EthSubscribe, Namespace as _};
+use web3::signing::Signature;
 use web3::transports::Http;
+#[cfg(unix)]
+use web3::transports::Ipc;
 use web3::types::Address;
-use web3::Web3;
+use web3::{Transport, Web3};
 
 /// Common data associated with multiple contracts.
 #[derive(Clone)]
-pub struct Web3Context(Arc<Web3ContextInner>);
+pub struct Web3Context {
+    inner: Arc<Web3ContextInner>,
+    /// The primary account, which sends unless another is chosen
+    from: Address,
+    signers: Arc<BTreeMap<Address, Arc<dyn Signer>>>,
+    fee_model: FeeModel,
+}
 
 pub trait Context {
     type Provider;
-    fn provider(&self, contract: Address, abi: &[u8]) -> Self::Provider;
+    /// The provider for a contract. The ABI is parsed once by the bindings,
+    /// and shared with the provider.
+    fn provider(&self, contract: Address, abi: Arc<ethabi::Contract>) -> Self::Provider;
+
+    /// Wraps the providers of this context with middleware. The layer added
+    /// last is outermost, so it sees calls first and results last.
+    fn with_layer<L>(self, layer: L) -> Layered<Self, L>
+    where
+        Self: Sized,
+        L: Layer<Self::Provider>,
+    {
+        Layered::new(self, layer)
+    }
 }
 
 struct Web3ContextInner {
-    from: Address,
-    secret_key: SafeSecretKey,
-    // We are not expecting to interact with the chain frequently,
-    // and the websocket transport has problems with ping.
-    // So, the Http transport seems like the best choice.
-    eth: Eth<Http>,
+    eth: Eth<BoxTransport>,
+    /// The transport of contexts connected with `ws`, kept unboxed so that
+    /// it can still subscribe
+    websocket: Option<ReconnectingWebSocket>,
+    confirmations: usize,
+    nonces: NonceManager,
 }
 
+// Num confirmations for sends which don't specify them. From a library
+// standpoint, this should be a parameter of the function. Choosing a correct
+// value is very difficult, even for a consumer of the library as it would
+// require assessing the value of the transaction, security margins, and a
+// number of other factors for which data may not be available. So just
+// picking a pretty high security margin for now.
+const DEFAULT_CONFIRMATIONS: usize = 24;
+
 impl Web3Context {
     pub fn new(
         url: &str,
         from: Address,
         secret_key: &SecretKey,
     ) -> Result<Self, web3::error::Error> {
+        // We are not expecting to interact with the chain frequently,
+        // and the websocket transport has problems with ping.
+        // So, the Http transport seems like the best choice. See `ws` and
+        // `ipc` for the alternatives.
         let transport = Http::new(url)?;
+        Ok(Self::with_transport(transport, from, secret_key))
+    }
+
+    /// Connects over WebSocket, reconnecting when the connection is lost.
+    /// See ReconnectingWebSocket. Subscriptions are made with
+    /// `eth_subscribe`. This must be used within a tokio runtime.
+    pub async fn ws(
+        url: &str,
+        from: Address,
+        secret_key: &SecretKey,
+    ) -> Result<Self, web3::error::Error> {
+        let transport = ReconnectingWebSocket::new(url).await?;
+        let signer = safe_secret_key(secret_key);
+        Ok(Self::build(
+            BoxTransport::new(transport.clone()),
+            Some(transport),
+            from,
+            signer,
+            DEFAULT_CONFIRMATIONS,
+        ))
+    }
+
+    /// Connects over IPC, eg: to the geth.ipc socket of a local node. This
+    /// must be used within a tokio runtime.
+    #[cfg(unix)]
+    pub async fn ipc(
+        path: impl AsRef<Path>,
+        from: Address,
+        secret_key: &SecretKey,
+    ) -> Result<Self, web3::error::Error> {
+        let transport = Ipc::new(path).await?;
+        Ok(Self::with_transport(transport, from, secret_key))
+    }
+
+    /// Uses any web3 transport, eg: BatchingHttp
+    pub fn with_transport<T>(transport: T, from: Address, secret_key: &SecretKey) -> Self
+    where
+        T: Transport + Send + Sync + 'static,
+        T::Out: Send + 'static,
+    {
+        let signer = safe_secret_key(secret_key);
+        Self::with_confirmations(transport, from, signer, DEFAULT_CONFIRMATIONS)
+    }
+
+    /// Uses any web3 transport, and sends from the account of the signer
+    pub fn with_signer<T>(transport: T, signer: impl Signer + 'static) -> Self
+    where
+        T: Transport + Send + Sync + 'static,
+        T::Out: Send + 'static,
+    {
+        let from = signer.address();
+        Self::with_confirmations(transport, from, Arc::new(signer), DEFAULT_CONFIRMATIONS)
+    }
+
+    /// Like `with_transport`, but with a different default number of
+    /// confirmations for sends. Chains which mine each transaction
+    /// immediately (eg: the in-process Evm) have no need to wait.
+    pub(crate) fn with_confirmations<T>(
+        transport: T,
+        from: Address,
+        signer: Arc<dyn Signer>,
+        confirmations: usize,
+    ) -> Self
+    where
+        T: Transport + Send + Sync + 'static,
+        T::Out: Send + 'static,
+    {
+        let transport = BoxTransport::new(transport);
+        Self::build(transport, None, from, signer, confirmations)
+    }
+
+    fn build(
+        transport: BoxTransport,
+        websocket: Option<ReconnectingWebSocket>,
+        from: Address,
+        signer: Arc<dyn Signer>,
+        confirmations: usize,
+    ) -> Self {
         let web3 = Web3::new(transport);
         let eth = web3.eth();
         let inner = Web3ContextInner {
             eth,
-            from,
-            secret_key: secret_key.try_into().unwrap(),
+            websocket,
+            confirmations,
+            nonces: NonceManager::default(),
         };
-        Ok(Self(Arc::new(inner)))
+        let mut signers = BTreeMap::new();
+        signers.insert(from, signer);
+        Self {
+            inner: Arc::new(inner),
+            from,
+            signers: Arc::new(signers),
+            fee_model: FeeModel::default(),
+        }
     }
 
+    /// Chooses between legacy and EIP-1559 transactions for sends, which
+    /// depends on what the chain supports. Defaults to legacy.
+    pub fn with_fee_model(mut self, fee_model: FeeModel) -> Self {
+        self.fee_model = fee_model;
+        self
+    }
+
+    /// Adds another account to send from, which contracts choose with
+    /// `with_account` or sends with the `from` option. The primary account
+    /// is still the default.
+    pub fn add_signer(mut self, signer: impl Signer + 'static) -> Self {
+        Arc::make_mut(&mut self.signers).insert(signer.address(), Arc::new(signer));
+        self
+    }
+
+    /// The primary account
     pub fn from(&self) -> Address {
-        self.0.from
+        self.from
+    }
+
+    /// The accounts which this context can send from
+    pub fn accounts(&self) -> impl Iterator<Item = Address> + '_ {
+        self.signers.keys().copied()
+    }
+
+    /// The signer of the primary account
+    pub fn signer(&self) -> &dyn Signer {
+        &*self.signers[&self.from]
+    }
+
+    /// Signs a message per EIP-191 (as personal_sign does) with the
+    /// primary account, eg: to log in to off-chain services. See
+    /// `recover_message` to verify it.
+    pub async fn sign_message(&self, message: &[u8]) -> Result<Signature, web3::Error> {
+        self.signer().sign_message(message).await
+    }
+
+    /// The signer of an account, if the context has one
+    pub fn signer_for(&self, account: Address) -> Option<&dyn Signer> {
+        self.signers.get(&account).map(|signer| &**signer)
+    }
+
+    /// The signer of an account, which it is an error not to have
+    pub(crate) fn signer_of(&self, account: Address) -> Result<&dyn Signer, web3::Error> {
+        self.signer_for(account).ok_or_else(|| {
+            web3::Error::InvalidResponse(format!("No signer for account {:?}", account))
+        })
+    }
+
+    pub(crate) fn confirmations(&self) -> usize {
+        self.inner.confirmations
     }
 
-    pub(crate) fn secret_key(&self) -> &SecretKey {
-        &self.0.secret_key
+    pub fn fee_model(&self) -> &FeeModel {
+        &self.fee_model
     }
 
-    pub(crate) fn eth(&self) -> Eth<Http> {
-        self.0.eth.clone()
+    pub(crate) fn nonces(&self) -> &NonceManager {
+        &self.inner.nonces
     }
+
+    pub(crate) fn eth(&self) -> Eth<BoxTransport> {
+        self.inner.eth.clone()
+    }
+
+    /// Subscribes to new blocks, logs and pending transactions. This is only
+    /// available to contexts connected with `ws`, and is None otherwise.
+    /// Subscriptions end when the connection is lost, see
+    /// ReconnectingWebSocket.
+    pub fn eth_subscribe(&self) -> Option<EthSubscribe<ReconnectingWebSocket>> {
+        self.inner.websocket.clone().map(EthSubscribe::new)
+    }
+}
+
+/// Moves a key into locked memory, to be signed with
+pub(crate) fn safe_secret_key(secret_key: &SecretKey) -> Arc<dyn Signer> {
+    let safe: SafeSecretKey = secret_key.try_into().unwrap();
+    Arc::new(safe)
 }
 
 impl Context for Web3Context {
     type Provider = Web3Provider;
-    fn provider(&self, contract: Address, json_abi: &[u8]) -> Self::Provider {
-        Web3Provider::new(contract, self, json_abi)
+    fn provider(&self, contract: Address, abi: Arc<ethabi::Contract>) -> Self::Provider {
+        Web3Provider::new(contract, self, abi)
     }
 }
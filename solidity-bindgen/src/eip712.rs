@@ -0,0 +1,482 @@
+use crate::signer::recover;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+use web3::signing::{keccak256, Signature};
+use web3::types::{Address, H256, U256};
+
+/// The struct types of typed data, by name
+pub type Types = BTreeMap<String, Vec<TypedDataField>>;
+
+/// Structured data to sign per EIP-712, in the JSON form of
+/// eth_signTypedData. This can be read from JSON, or made from a Rust
+/// struct which implements Eip712.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TypedData {
+    pub types: Types,
+    pub primary_type: String,
+    pub domain: Eip712Domain,
+    pub message: Value,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TypedDataField {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+}
+
+/// Where a signature is valid, so that it can't be replayed elsewhere.
+/// Fields which are not given are left out of the domain.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Eip712Domain {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "chain_id"
+    )]
+    pub chain_id: Option<U256>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub verifying_contract: Option<Address>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub salt: Option<H256>,
+}
+
+impl Eip712Domain {
+    /// The EIP712Domain type, with the fields which are given
+    fn fields(&self) -> Vec<TypedDataField> {
+        let fields = [
+            ("name", "string", self.name.is_some()),
+            ("version", "string", self.version.is_some()),
+            ("chainId", "uint256", self.chain_id.is_some()),
+            (
+                "verifyingContract",
+                "address",
+                self.verifying_contract.is_some(),
+            ),
+            ("salt", "bytes32", self.salt.is_some()),
+        ];
+        fields
+            .iter()
+            .filter(|(_, _, given)| *given)
+            .map(|(name, kind, _)| TypedDataField {
+                name: (*name).to_owned(),
+                kind: (*kind).to_owned(),
+            })
+            .collect()
+    }
+}
+
+/// A Rust struct which can be signed as typed data. Its serde form is the
+/// message, so must have the fields of the type.
+///
+/// ```ignore
+/// #[derive(Serialize)]
+/// struct Permit { owner: Address, spender: Address, value: U256, nonce: U256, deadline: U256 }
+///
+/// impl Eip712 for Permit {
+///     const TYPE: &'static str =
+///         "Permit(address owner,address spender,uint256 value,uint256 nonce,uint256 deadline)";
+/// }
+/// ```
+pub trait Eip712: Serialize {
+    /// The encoded type, as hashed for the type hash. This is the struct
+    /// type followed by any struct types it refers to.
+    const TYPE: &'static str;
+}
+
+const DOMAIN_TYPE: &str = "EIP712Domain";
+
+impl TypedData {
+    pub fn from_struct<T: Eip712>(domain: Eip712Domain, value: &T) -> Result<Self, web3::Error> {
+        let mut types = parse_types(T::TYPE)?;
+        let primary_type = T::TYPE.split('(').next().unwrap_or_default().to_owned();
+        types.insert(DOMAIN_TYPE.to_owned(), domain.fields());
+        let message = serde_json::to_value(value)?;
+        Ok(Self {
+            types,
+            primary_type,
+            domain,
+            message,
+        })
+    }
+
+    /// The hash which is signed
+    pub fn hash(&self) -> Result<H256, web3::Error> {
+        let mut encoded = vec![0x19, 0x01];
+        encoded.extend_from_slice(self.domain_separator()?.as_bytes());
+        encoded.extend_from_slice(self.struct_hash()?.as_bytes());
+        Ok(H256(keccak256(&encoded)))
+    }
+
+    pub fn domain_separator(&self) -> Result<H256, web3::Error> {
+        let domain = serde_json::to_value(&self.domain)?;
+        if self.types.contains_key(DOMAIN_TYPE) {
+            hash_struct(&self.types, DOMAIN_TYPE, &domain)
+        } else {
+            let mut types = self.types.clone();
+            types.insert(DOMAIN_TYPE.to_owned(), self.domain.fields());
+            hash_struct(&types, DOMAIN_TYPE, &domain)
+        }
+        .map(H256)
+    }
+
+    /// The hash of the message
+    pub fn struct_hash(&self) -> Result<H256, web3::Error> {
+        hash_struct(&self.types, &self.primary_type, &self.message).map(H256)
+    }
+
+    /// The account which signed the typed data
+    pub fn recover(&self, signature: &Signature) -> Result<Address, web3::Error> {
+        recover(&self.hash()?.0, signature)
+    }
+}
+
+fn hash_struct(types: &Types, kind: &str, value: &Value) -> Result<[u8; 32], web3::Error> {
+    let fields = types
+        .get(kind)
+        .ok_or_else(|| invalid(format!("no type {}", kind)))?;
+    let mut encoded = Vec::with_capacity(32 * (fields.len() + 1));
+    encoded.extend_from_slice(&keccak256(encode_type(types, kind)?.as_bytes()));
+    for field in fields {
+        let value = value
+            .get(&field.name)
+            .ok_or_else(|| invalid(format!("{} has no {}", kind, field.name)))?;
+        encoded.extend_from_slice(&encode_value(types, &field.kind, value)?);
+    }
+    Ok(keccak256(&encoded))
+}
+
+fn encode_value(types: &Types, kind: &str, value: &Value) -> Result<[u8; 32], web3::Error> {
+    let wrong = || invalid(format!("{} is not a {}", value, kind));
+    if let Some(element) = array_element(kind) {
+        let values = value.as_array().ok_or_else(wrong)?;
+        let mut encoded = Vec::with_capacity(32 * values.len());
+        for value in values {
+            encoded.extend_from_slice(&encode_value(types, element, value)?);
+        }
+        return Ok(keccak256(&encoded));
+    }
+    if types.contains_key(kind) {
+        return hash_struct(types, kind, value);
+    }
+
+    let mut word = [0u8; 32];
+    match kind {
+        "string" => return Ok(keccak256(value.as_str().ok_or_else(wrong)?.as_bytes())),
+        "bytes" => return Ok(keccak256(&bytes(value).ok_or_else(wrong)?)),
+        "bool" => word[31] = value.as_bool().ok_or_else(wrong)? as u8,
+        "address" => {
+            let address: Address = serde_json::from_value(value.clone()).map_err(|_| wrong())?;
+            word[12..].copy_from_slice(address.as_bytes());
+        }
+        _ => {
+            if let Some(len) = kind.strip_prefix("bytes").and_then(|n| n.parse().ok()) {
+                let bytes = bytes(value).filter(|b| b.len() == len && len <= 32);
+                let bytes = bytes.ok_or_else(wrong)?;
+                word[..len].copy_from_slice(&bytes);
+            } else if let Some(bits) = int_bits(kind, "uint") {
+                let (negative, magnitude) = integer(value).ok_or_else(wrong)?;
+                if negative || magnitude.bits() > bits {
+                    return Err(wrong());
+                }
+                magnitude.to_big_endian(&mut word);
+            } else if let Some(bits) = int_bits(kind, "int") {
+                let (negative, magnitude) = integer(value).ok_or_else(wrong)?;
+                let limit = U256::one() << (bits - 1);
+                if (negative && magnitude > limit) || (!negative && magnitude >= limit) {
+                    return Err(wrong());
+                }
+                let value = if negative {
+                    (!magnitude).overflowing_add(U256::one()).0
+                } else {
+                    magnitude
+                };
+                value.to_big_endian(&mut word);
+            } else {
+                return Err(invalid(format!("no type {}", kind)));
+            }
+        }
+    }
+    Ok(word)
+}
+
+/// Encodes a struct type, followed by the struct types it refers to in
+/// order of name
+fn encode_type(types: &Types, kind: &str) -> Result<String, web3::Error> {
+    let mut referenced = BTreeSet::new();
+    references(types, kind, &mut referenced);
+    referenced.remove(kind);
+    let mut encoded = String::new();
+    for kind in std::iter::once(kind).chain(referenced.iter().map(String::as_str)) {
+        let fields = types
+            .get(kind)
+            .ok_or_else(|| invalid(format!("no type {}", kind)))?;
+        let fields: Vec<_> = fields
+            .iter()
+            .map(|field| format!("{} {}", field.kind, field.name))
+            .collect();
+        encoded.push_str(&format!("{}({})", kind, fields.join(",")));
+    }
+    Ok(encoded)
+}
+
+fn references(types: &Types, kind: &str, found: &mut BTreeSet<String>) {
+    let mut kind = kind;
+    while let Some(element) = array_element(kind) {
+        kind = element;
+    }
+    if let Some(fields) = types.get(kind) {
+        if found.insert(kind.to_owned()) {
+            for field in fields {
+                references(types, &field.kind, found);
+            }
+        }
+    }
+}
+
+/// Reads an encoded type, eg: "Mail(Person from,string contents)Person(..)"
+fn parse_types(encoded: &str) -> Result<Types, web3::Error> {
+    let mut types = Types::new();
+    let mut rest = encoded;
+    while !rest.is_empty() {
+        let malformed = || invalid(format!("malformed type {}", encoded));
+        let open = rest.find('(').ok_or_else(malformed)?;
+        let close = rest.find(')').ok_or_else(malformed)?;
+        let fields = &rest[open + 1..close];
+        let fields = fields
+            .split(',')
+            .filter(|field| !field.is_empty())
+            .map(|field| match field.split(' ').collect::<Vec<_>>()[..] {
+                [kind, name] => Ok(TypedDataField {
+                    name: name.to_owned(),
+                    kind: kind.to_owned(),
+                }),
+                _ => Err(malformed()),
+            })
+            .collect::<Result<_, _>>()?;
+        types.insert(rest[..open].to_owned(), fields);
+        rest = &rest[close + 1..];
+    }
+    Ok(types)
+}
+
+/// The type of the elements of an array type, eg: Person of Person[3]
+fn array_element(kind: &str) -> Option<&str> {
+    kind.strip_suffix(']')
+        .and_then(|kind| kind.rfind('[').map(|open| &kind[..open]))
+}
+
+/// The bits of eg: uint64, which must be a multiple of 8 up to 256
+fn int_bits(kind: &str, prefix: &str) -> Option<usize> {
+    let bits = kind.strip_prefix(prefix)?;
+    let bits = if bits.is_empty() {
+        256
+    } else {
+        bits.parse().ok()?
+    };
+    Some(bits).filter(|bits| bits % 8 == 0 && (8..=256).contains(bits))
+}
+
+/// Bytes from hex, or an array of numbers as serde writes Vec<u8>
+fn bytes(value: &Value) -> Option<Vec<u8>> {
+    match value {
+        Value::String(s) => hex::decode(s.strip_prefix("0x").unwrap_or(s)).ok(),
+        Value::Array(values) => values
+            .iter()
+            .map(|value| value.as_u64().filter(|b| *b <= 255).map(|b| b as u8))
+            .collect(),
+        _ => None,
+    }
+}
+
+/// The sign and magnitude of an integer, from a number, a decimal string,
+/// or a hex string (as U256 is written)
+fn integer(value: &Value) -> Option<(bool, U256)> {
+    match value {
+        Value::Number(n) => match (n.as_u64(), n.as_i64()) {
+            (Some(n), _) => Some((false, n.into())),
+            (None, Some(n)) => Some((true, n.unsigned_abs().into())),
+            _ => None,
+        },
+        Value::String(s) => {
+            let (negative, s) = match s.strip_prefix('-') {
+                Some(s) => (true, s),
+                None => (false, s.as_str()),
+            };
+            let magnitude = match s.strip_prefix("0x") {
+                Some(hex) => U256::from_str_radix(hex, 16).ok()?,
+                None => U256::from_dec_str(s).ok()?,
+            };
+            Some((negative && !magnitude.is_zero(), magnitude))
+        }
+        _ => None,
+    }
+}
+
+/// Chain ids are often numbers in typed data, rather than hex as U256 is
+fn chain_id<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<U256>, D::Error> {
+    match Option::<Value>::deserialize(deserializer)? {
+        None => Ok(None),
+        Some(value) => match integer(&value) {
+            Some((false, chain_id)) => Ok(Some(chain_id)),
+            _ => Err(serde::de::Error::custom(format!(
+                "{} is not a chain id",
+                value
+            ))),
+        },
+    }
+}
+
+fn invalid(reason: String) -> web3::Error {
+    web3::Error::Decoder(format!("Invalid EIP-712 typed data: {}", reason))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{SafeSecretKey, Signer};
+    use serde_json::json;
+
+    /// The example of EIP-712
+    fn mail() -> TypedData {
+        serde_json::from_value(json!({
+            "types": {
+                "EIP712Domain": [
+                    {"name": "name", "type": "string"},
+                    {"name": "version", "type": "string"},
+                    {"name": "chainId", "type": "uint256"},
+                    {"name": "verifyingContract", "type": "address"}
+                ],
+                "Person": [
+                    {"name": "name", "type": "string"},
+                    {"name": "wallet", "type": "address"}
+                ],
+                "Mail": [
+                    {"name": "from", "type": "Person"},
+                    {"name": "to", "type": "Person"},
+                    {"name": "contents", "type": "string"}
+                ]
+            },
+            "primaryType": "Mail",
+            "domain": {
+                "name": "Ether Mail",
+                "version": "1",
+                "chainId": 1,
+                "verifyingContract": "0xCcCCccccCCCCcCCCCCCcCcCccCcCCCcCcccccccC"
+            },
+            "message": {
+                "from": {"name": "Cow", "wallet": "0xCD2a3d9F938E13CD947Ec05AbC7FE734Df8DD826"},
+                "to": {"name": "Bob", "wallet": "0xbBbBBBBbbBBBbbbBbbBbbbbBBbBbbbbBbBbbBBbB"},
+                "contents": "Hello, Bob!"
+            }
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    pub fn hashes_example() {
+        let mail = mail();
+        assert_eq!(
+            encode_type(&mail.types, "Mail").unwrap(),
+            "Mail(Person from,Person to,string contents)Person(string name,address wallet)"
+        );
+        assert_eq!(
+            mail.domain_separator().unwrap(),
+            "f2cee375fa42b42143804025fc449deafd50cc031ca257e0b194a650a912090f"
+                .parse()
+                .unwrap()
+        );
+        assert_eq!(
+            mail.struct_hash().unwrap(),
+            "c52c0ee5d84264471806290a3f2c4cecfc5490626bf912d01f240d7a274b371e"
+                .parse()
+                .unwrap()
+        );
+        assert_eq!(
+            mail.hash().unwrap(),
+            "be609aee343fb3c4b28e1df9e632fca64fcfaede20f02e86244efddf30957bd2"
+                .parse()
+                .unwrap()
+        );
+    }
+
+    #[test]
+    pub fn signs_and_recovers() {
+        let mail = mail();
+        let cow = SafeSecretKey::from_bytes(&keccak256(b"cow")).unwrap();
+        let signature = futures::executor::block_on(cow.sign_typed_data(&mail)).unwrap();
+        assert_eq!(signature.v, 28);
+        assert_eq!(
+            signature.r,
+            "4355c47d63924e8a72e509b65029052eb6c299d53a04e167c5775fd466751c9d"
+                .parse()
+                .unwrap()
+        );
+        assert_eq!(
+            signature.s,
+            "07299936d304c153f6443dfa05f40ff007d72911b6f72307f996231605b91562"
+                .parse()
+                .unwrap()
+        );
+        assert_eq!(mail.recover(&signature).unwrap(), cow.address());
+    }
+
+    #[derive(Serialize)]
+    struct Person {
+        name: String,
+        wallet: Address,
+    }
+
+    #[derive(Serialize)]
+    struct Mail {
+        from: Person,
+        to: Person,
+        contents: String,
+    }
+
+    impl Eip712 for Mail {
+        const TYPE: &'static str =
+            "Mail(Person from,Person to,string contents)Person(string name,address wallet)";
+    }
+
+    #[test]
+    pub fn hashes_structs() {
+        let expected = mail();
+        let mail = Mail {
+            from: Person {
+                name: "Cow".to_owned(),
+                wallet: "CD2a3d9F938E13CD947Ec05AbC7FE734Df8DD826".parse().unwrap(),
+            },
+            to: Person {
+                name: "Bob".to_owned(),
+                wallet: "bBbBBBBbbBBBbbbBbbBbbbbBBbBbbbbBbBbbBBbB".parse().unwrap(),
+            },
+            contents: "Hello, Bob!".to_owned(),
+        };
+        let typed = TypedData::from_struct(expected.domain.clone(), &mail).unwrap();
+        assert_eq!(typed.types, expected.types);
+        assert_eq!(typed.hash().unwrap(), expected.hash().unwrap());
+    }
+
+    #[test]
+    pub fn encodes_integers() {
+        let types = Types::new();
+        let encode = |kind: &str, value: Value| encode_value(&types, kind, &value).ok();
+        let mut minus_one = [0xff; 32];
+        assert_eq!(encode("int8", json!(-1)), Some(minus_one));
+        assert_eq!(encode("int256", json!("-1")), Some(minus_one));
+        minus_one[..31].copy_from_slice(&[0; 31]);
+        assert_eq!(encode("uint8", json!("0xff")), Some(minus_one));
+        assert_eq!(encode("uint8", json!(256)), None);
+        assert_eq!(encode("int8", json!(128)), None);
+        assert_eq!(encode("int8", json!(-129)), None);
+        assert_eq!(encode("uint7", json!(1)), None);
+    }
+}
@@ -0,0 +1,1136 @@
+use crate::context::safe_secret_key;
+use crate::Web3Context;
+use jsonrpc_core as rpc;
+use revm::db::{AccountState, InMemoryDB};
+use revm::inspectors::NoOpInspector;
+use revm::interpreter::{opcode, Interpreter};
+use revm::primitives::{
+    AccountInfo, Bytecode, ExecutionResult, Output, SpecId, TxKind, U256 as Word,
+};
+use revm::{inspector_handle_register, Database, EvmContext, Inspector};
+use rlp::Rlp;
+use secp256k1::key::SecretKey;
+use serde::de::DeserializeOwned;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use web3::signing::{keccak256, recover, Key as _, SecretKeyRef};
+use web3::types::{
+    AccessList, AccessListItem, Address, Block, Bytes, CallRequest, Log, Transaction,
+    TransactionReceipt, H256, U256, U64,
+};
+use web3::{helpers, RequestId, Transport};
+
+/// The chain id used by `Evm::new`, which is the one used by most dev chains
+pub const DEFAULT_CHAIN_ID: u64 = 1337;
+
+const BLOCK_GAS_LIMIT: u64 = 30_000_000;
+
+/// 1 gwei. The base fee is always 0, so any price is accepted.
+const GAS_PRICE: u64 = 1_000_000_000;
+
+/// An Ethereum chain executed in-process, for tests of code using generated
+/// bindings which should neither need a node nor mock out the contracts.
+///
+/// The chain starts with an empty genesis block. Accounts are funded and
+/// contracts deployed directly, without transactions. After that, each
+/// transaction sent through a context from `Evm::context` is executed and
+/// mined into its own block immediately, so its receipt is confirmed as
+/// soon as it is sent.
+///
+/// ```ignore
+/// let evm = Evm::new();
+/// let context = evm.context(&secret_key);
+/// evm.fund(context.from(), U256::exp10(18));
+/// let address = evm.deploy(context.from(), bytecode)?;
+///
+/// let token = Token::new(address, &context);
+/// token.transfer(to, 10.into()).await?;
+/// assert_eq!(token.balance_of(to).await?, 10.into());
+/// ```
+///
+/// Only the latest state is kept, so calls at earlier blocks see the latest
+/// state. Logs are available from receipts, but not from filters.
+#[derive(Clone)]
+pub struct Evm {
+    id: Arc<AtomicUsize>,
+    chain: Arc<Mutex<Chain>>,
+}
+
+struct Chain {
+    chain_id: u64,
+    db: InMemoryDB,
+    blocks: Vec<Block<H256>>,
+    transactions: HashMap<H256, (Transaction, TransactionReceipt)>,
+    /// For each block filter, the number of the next block to report
+    filters: HashMap<U256, u64>,
+    next_filter: u64,
+}
+
+/// A message call or contract creation to execute
+#[derive(Clone)]
+struct Message {
+    caller: Address,
+    to: Option<Address>,
+    value: U256,
+    data: Vec<u8>,
+    gas: u64,
+    gas_price: U256,
+    /// The priority fee of an EIP-1559 transaction, whose gas price is then
+    /// its max fee
+    gas_priority_fee: Option<U256>,
+    /// Checked against the caller's nonce when Some
+    nonce: Option<u64>,
+    access_list: AccessList,
+}
+
+impl Evm {
+    pub fn new() -> Self {
+        Self::with_chain_id(DEFAULT_CHAIN_ID)
+    }
+
+    pub fn with_chain_id(chain_id: u64) -> Self {
+        let mut chain = Chain {
+            chain_id,
+            db: InMemoryDB::default(),
+            blocks: Vec::new(),
+            transactions: HashMap::new(),
+            filters: HashMap::new(),
+            next_filter: 1,
+        };
+        let timestamp = chain.next_timestamp();
+        chain.mine(Vec::new(), timestamp);
+        Self {
+            id: Arc::new(AtomicUsize::new(1)),
+            chain: Arc::new(Mutex::new(chain)),
+        }
+    }
+
+    /// A context which signs with `secret_key` and sends to this chain
+    pub fn context(&self, secret_key: &SecretKey) -> Web3Context {
+        let from = SecretKeyRef::new(secret_key).address();
+        Web3Context::with_confirmations(self.clone(), from, safe_secret_key(secret_key), 0)
+    }
+
+    /// Sets the balance of an account
+    pub fn fund(&self, address: Address, balance: U256) {
+        let mut chain = self.chain.lock().unwrap();
+        chain.update_account(address, |info| info.balance = word(balance));
+    }
+
+    pub fn balance(&self, address: Address) -> U256 {
+        u256(self.chain.lock().unwrap().account(address).balance)
+    }
+
+    /// Sets the runtime code of an account, eg: to put a contract at a well
+    /// known address like that of Multicall3.
+    pub fn set_code(&self, address: Address, code: Vec<u8>) {
+        let mut chain = self.chain.lock().unwrap();
+        chain.update_account(address, |info| {
+            let code = Bytecode::new_raw(code.into());
+            info.code_hash = code.hash_slow();
+            info.code = Some(code);
+        });
+    }
+
+    /// Runs the creation bytecode (with any constructor args appended) as
+    /// `from` and mines a block, returning the address of the new contract.
+    /// No signature is needed, and `from` pays no gas.
+    pub fn deploy(&self, from: Address, bytecode: Vec<u8>) -> web3::Result<Address> {
+        let mut chain = self.chain.lock().unwrap();
+        let timestamp = chain.next_timestamp();
+        let message = Message {
+            caller: from,
+            to: None,
+            value: U256::zero(),
+            data: bytecode,
+            gas: BLOCK_GAS_LIMIT,
+            gas_price: U256::zero(),
+            gas_priority_fee: None,
+            nonce: None,
+            access_list: AccessList::new(),
+        };
+        let result = chain.transact(message, timestamp, true)?;
+        let address = match result {
+            ExecutionResult::Success {
+                output: Output::Create(_, Some(address)),
+                ..
+            } => h160(address),
+            result => return Err(failure(result).into()),
+        };
+        chain.mine(Vec::new(), timestamp);
+        Ok(address)
+    }
+
+    /// Mines an empty block, eg: to advance block.number or block.timestamp
+    pub fn mine(&self) {
+        let mut chain = self.chain.lock().unwrap();
+        let timestamp = chain.next_timestamp();
+        chain.mine(Vec::new(), timestamp);
+    }
+
+    pub fn block_number(&self) -> u64 {
+        self.chain.lock().unwrap().latest()
+    }
+}
+
+impl Default for Evm {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for Evm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let chain = self.chain.lock().unwrap();
+        f.debug_struct("Evm")
+            .field("chain_id", &chain.chain_id)
+            .field("block_number", &chain.latest())
+            .finish()
+    }
+}
+
+impl Transport for Evm {
+    type Out = futures::future::Ready<web3::Result<rpc::Value>>;
+
+    fn prepare(&self, method: &str, params: Vec<rpc::Value>) -> (RequestId, rpc::Call) {
+        let id = self.id.fetch_add(1, Ordering::AcqRel);
+        (id, helpers::build_request(id, method, params))
+    }
+
+    fn send(&self, _id: RequestId, request: rpc::Call) -> Self::Out {
+        let result = match request {
+            rpc::Call::MethodCall(call) => {
+                let params = match call.params {
+                    rpc::Params::Array(params) => params,
+                    _ => Vec::new(),
+                };
+                let mut chain = self.chain.lock().unwrap();
+                chain.handle(&call.method, params).map_err(web3::Error::Rpc)
+            }
+            _ => Err(web3::Error::Rpc(rpc::Error::invalid_request())),
+        };
+        futures::future::ready(result)
+    }
+}
+
+impl Chain {
+    fn handle(&mut self, method: &str, params: Vec<rpc::Value>) -> Result<rpc::Value, rpc::Error> {
+        let result = match method {
+            "eth_chainId" => helpers::serialize(&U64::from(self.chain_id)),
+            "net_version" => rpc::Value::String(self.chain_id.to_string()),
+            "eth_blockNumber" => helpers::serialize(&U64::from(self.latest())),
+            "eth_gasPrice" => helpers::serialize(&U256::from(GAS_PRICE)),
+            "eth_feeHistory" => {
+                let count: U256 = param(&params, 0)?;
+                let newest = self.block_tag(params.get(1))?;
+                let percentiles: Vec<f64> = param(&params, 2).unwrap_or_default();
+                let count = count.low_u64().min(newest + 1);
+                // Every block has a base fee of 0, and the priority fees paid
+                // aren't kept, so none are reported
+                serde_json::json!({
+                    "oldestBlock": U64::from(newest + 1 - count),
+                    "baseFeePerGas": vec![U256::zero(); count as usize + 1],
+                    "gasUsedRatio": vec![0.0; count as usize],
+                    "reward": vec![vec![U256::zero(); percentiles.len()]; count as usize],
+                })
+            }
+            "eth_getBalance" => {
+                let address = param(&params, 0)?;
+                helpers::serialize(&u256(self.account(address).balance))
+            }
+            "eth_getTransactionCount" => {
+                let address = param(&params, 0)?;
+                helpers::serialize(&U256::from(self.account(address).nonce))
+            }
+            "eth_getCode" => {
+                let address = param(&params, 0)?;
+                let code = self
+                    .account(address)
+                    .code
+                    .map(|code| code.original_bytes().to_vec())
+                    .unwrap_or_default();
+                helpers::serialize(&Bytes(code))
+            }
+            "eth_getStorageAt" => {
+                let address: Address = param(&params, 0)?;
+                let slot: U256 = param(&params, 1)?;
+                let value = self
+                    .db
+                    .load_account(revm_address(address))
+                    .ok()
+                    .and_then(|account| account.storage.get(&word(slot)).copied())
+                    .unwrap_or_default();
+                helpers::serialize(&H256(value.to_be_bytes()))
+            }
+            "eth_call" => {
+                let request = param(&params, 0)?;
+                let timestamp = self.next_timestamp();
+                match self.transact(message(request), timestamp, false)? {
+                    ExecutionResult::Success { output, .. } => {
+                        helpers::serialize(&Bytes(output.into_data().to_vec()))
+                    }
+                    result => return Err(failure(result)),
+                }
+            }
+            "eth_estimateGas" => {
+                let request = param(&params, 0)?;
+                let mut message = message(request);
+                message.gas = BLOCK_GAS_LIMIT;
+                let timestamp = self.next_timestamp();
+                match self.transact(message, timestamp, false)? {
+                    // Refunds are only paid at the end, so the limit must
+                    // cover them. And each nested call can only be given
+                    // 63/64ths of the remaining gas. Rather than search for
+                    // the lowest limit that succeeds, allow for both.
+                    ExecutionResult::Success {
+                        gas_used,
+                        gas_refunded,
+                        ..
+                    } => {
+                        let gas = (gas_used + gas_refunded) * 64 / 63;
+                        helpers::serialize(&U256::from(gas.min(BLOCK_GAS_LIMIT)))
+                    }
+                    result => return Err(failure(result)),
+                }
+            }
+            "eth_createAccessList" => {
+                let request: CallRequest = param(&params, 0)?;
+                let mut message = message(request);
+                let mut tracer = AccessListTracer::new(&message);
+                let timestamp = self.next_timestamp();
+                match self.inspect(message.clone(), timestamp, false, &mut tracer)? {
+                    ExecutionResult::Success { .. } => {}
+                    result => return Err(failure(result)),
+                }
+                // The gas used is with the list
+                message.access_list = tracer.access_list();
+                match self.transact(message.clone(), timestamp, false)? {
+                    ExecutionResult::Success { gas_used, .. } => serde_json::json!({
+                        "accessList": message.access_list,
+                        "gasUsed": U256::from(gas_used),
+                    }),
+                    result => return Err(failure(result)),
+                }
+            }
+            "eth_sendRawTransaction" => {
+                let raw: Bytes = param(&params, 0)?;
+                helpers::serialize(&self.send_raw(&raw.0)?)
+            }
+            "eth_getTransactionByHash" => {
+                let hash: H256 = param(&params, 0)?;
+                helpers::serialize(&self.transactions.get(&hash).map(|(tx, _)| tx))
+            }
+            "eth_getTransactionReceipt" => {
+                let hash: H256 = param(&params, 0)?;
+                helpers::serialize(&self.transactions.get(&hash).map(|(_, receipt)| receipt))
+            }
+            "eth_getBlockByNumber" => {
+                let number = self.block_tag(params.first())?;
+                let full = param(&params, 1).unwrap_or(false);
+                self.block(self.blocks.get(number as usize), full)
+            }
+            "eth_getBlockByHash" => {
+                let hash: H256 = param(&params, 0)?;
+                let full = param(&params, 1).unwrap_or(false);
+                let block = self.blocks.iter().find(|b| b.hash == Some(hash));
+                self.block(block, full)
+            }
+            "eth_newBlockFilter" => {
+                let id = U256::from(self.next_filter);
+                self.next_filter += 1;
+                self.filters.insert(id, self.latest() + 1);
+                helpers::serialize(&id)
+            }
+            "eth_getFilterChanges" => {
+                let id: U256 = param(&params, 0)?;
+                let latest = self.latest();
+                let next = self
+                    .filters
+                    .get_mut(&id)
+                    .ok_or_else(|| server_error("filter not found".to_owned()))?;
+                let hashes: Vec<H256> = self.blocks[*next as usize..]
+                    .iter()
+                    .filter_map(|b| b.hash)
+                    .collect();
+                *next = latest + 1;
+                helpers::serialize(&hashes)
+            }
+            "eth_uninstallFilter" => {
+                let id: U256 = param(&params, 0)?;
+                rpc::Value::Bool(self.filters.remove(&id).is_some())
+            }
+            _ => return Err(rpc::Error::method_not_found()),
+        };
+        Ok(result)
+    }
+
+    fn latest(&self) -> u64 {
+        self.blocks.len() as u64 - 1
+    }
+
+    fn next_timestamp(&self) -> u64 {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        match self.blocks.last() {
+            Some(parent) => now.max(parent.timestamp.low_u64() + 1),
+            None => now,
+        }
+    }
+
+    fn account(&mut self, address: Address) -> AccountInfo {
+        self.db
+            .load_account(revm_address(address))
+            .ok()
+            .and_then(|account| account.info())
+            .unwrap_or_default()
+    }
+
+    fn update_account(&mut self, address: Address, f: impl FnOnce(&mut AccountInfo)) {
+        let mut info = self.account(address);
+        f(&mut info);
+        let address = revm_address(address);
+        // The empty database can't fail
+        let account = self.db.load_account(address).unwrap();
+        if matches!(account.account_state, AccountState::NotExisting) {
+            account.account_state = AccountState::None;
+        }
+        self.db.insert_account_info(address, info);
+    }
+
+    fn block_tag(&self, tag: Option<&rpc::Value>) -> Result<u64, rpc::Error> {
+        match tag.and_then(|t| t.as_str()) {
+            Some("earliest") => Ok(0),
+            Some("latest") | Some("pending") | Some("safe") | Some("finalized") | None => {
+                Ok(self.latest())
+            }
+            Some(number) => {
+                let number: U64 = serde_json::from_value(rpc::Value::String(number.to_owned()))
+                    .map_err(|e| invalid_params(format!("Invalid block: {}", e)))?;
+                Ok(number.as_u64())
+            }
+        }
+    }
+
+    fn block(&self, block: Option<&Block<H256>>, full: bool) -> rpc::Value {
+        match block {
+            Some(block) if full => {
+                let transactions: Vec<&Transaction> = block
+                    .transactions
+                    .iter()
+                    .map(|hash| &self.transactions[hash].0)
+                    .collect();
+                let mut block = helpers::serialize(block);
+                block["transactions"] = helpers::serialize(&transactions);
+                block
+            }
+            block => helpers::serialize(&block),
+        }
+    }
+
+    /// Executes against the pending block. When `commit` is false the state
+    /// is left unchanged, as for eth_call.
+    fn transact(
+        &mut self,
+        message: Message,
+        timestamp: u64,
+        commit: bool,
+    ) -> Result<ExecutionResult, rpc::Error> {
+        self.inspect(message, timestamp, commit, NoOpInspector)
+    }
+
+    /// Like `transact`, with each step of execution seen by an inspector
+    fn inspect<I>(
+        &mut self,
+        message: Message,
+        timestamp: u64,
+        commit: bool,
+        inspector: I,
+    ) -> Result<ExecutionResult, rpc::Error>
+    where
+        I: for<'db> Inspector<&'db mut InMemoryDB>,
+    {
+        let number = self.blocks.len() as u64;
+        let chain_id = self.chain_id;
+        let mut evm = revm::Evm::builder()
+            .with_db(&mut self.db)
+            .with_external_context(inspector)
+            .modify_cfg_env(|cfg| cfg.chain_id = chain_id)
+            .modify_block_env(|block| {
+                block.number = Word::from(number);
+                block.timestamp = Word::from(timestamp);
+                block.gas_limit = Word::from(BLOCK_GAS_LIMIT);
+            })
+            .modify_tx_env(|tx| {
+                tx.caller = revm_address(message.caller);
+                tx.transact_to = match message.to {
+                    Some(to) => TxKind::Call(revm_address(to)),
+                    None => TxKind::Create,
+                };
+                tx.value = word(message.value);
+                tx.data = message.data.into();
+                tx.gas_limit = message.gas;
+                tx.gas_price = word(message.gas_price);
+                tx.gas_priority_fee = message.gas_priority_fee.map(word);
+                tx.nonce = message.nonce;
+                tx.access_list = message
+                    .access_list
+                    .into_iter()
+                    .map(|item| {
+                        let keys = item
+                            .storage_keys
+                            .iter()
+                            .map(|key| Word::from_be_bytes(key.0));
+                        (revm_address(item.address), keys.collect())
+                    })
+                    .collect();
+            })
+            .with_spec_id(SpecId::CANCUN)
+            .append_handler_register(inspector_handle_register)
+            .build();
+        let result = if commit {
+            evm.transact_commit()
+        } else {
+            evm.transact().map(|result| result.result)
+        };
+        result.map_err(|e| server_error(format!("{:?}", e)))
+    }
+
+    /// Executes a signed transaction and mines it into a new block
+    fn send_raw(&mut self, raw: &[u8]) -> Result<H256, rpc::Error> {
+        let (mut tx, gas_priority_fee) = decode_transaction(raw, self.chain_id)?;
+        let message = Message {
+            caller: tx.from.unwrap_or_default(),
+            to: tx.to,
+            value: tx.value,
+            data: tx.input.0.clone(),
+            gas: tx.gas.low_u64(),
+            gas_price: tx.gas_price,
+            gas_priority_fee,
+            nonce: Some(tx.nonce.low_u64()),
+            access_list: tx.access_list.clone().unwrap_or_default(),
+        };
+        let timestamp = self.next_timestamp();
+        let result = self.transact(message, timestamp, true)?;
+
+        let number = self.blocks.len() as u64;
+        let block_hash = block_hash(number);
+        tx.block_hash = Some(block_hash);
+        tx.block_number = Some(number.into());
+        tx.transaction_index = Some(0.into());
+
+        let (status, gas_used, logs, contract_address) = match result {
+            ExecutionResult::Success {
+                gas_used,
+                logs,
+                output,
+                ..
+            } => {
+                let contract_address = match output {
+                    Output::Create(_, address) => address.map(h160),
+                    Output::Call(_) => None,
+                };
+                (1, gas_used, logs, contract_address)
+            }
+            ExecutionResult::Revert { gas_used, .. } | ExecutionResult::Halt { gas_used, .. } => {
+                (0, gas_used, Vec::new(), None)
+            }
+        };
+        let logs = logs
+            .into_iter()
+            .enumerate()
+            .map(|(index, log)| Log {
+                address: h160(log.address),
+                topics: log.topics().iter().map(|t| H256(t.0)).collect(),
+                data: Bytes(log.data.data.to_vec()),
+                block_hash: Some(block_hash),
+                block_number: Some(number.into()),
+                transaction_hash: Some(tx.hash),
+                transaction_index: Some(0.into()),
+                log_index: Some(index.into()),
+                transaction_log_index: Some(index.into()),
+                log_type: None,
+                removed: Some(false),
+            })
+            .collect();
+        let receipt = TransactionReceipt {
+            transaction_hash: tx.hash,
+            transaction_index: 0.into(),
+            block_hash: Some(block_hash),
+            block_number: Some(number.into()),
+            cumulative_gas_used: gas_used.into(),
+            gas_used: Some(gas_used.into()),
+            contract_address,
+            logs,
+            status: Some(status.into()),
+            transaction_type: tx.transaction_type,
+            ..Default::default()
+        };
+
+        let hash = tx.hash;
+        self.transactions.insert(hash, (tx, receipt));
+        self.mine(vec![hash], timestamp);
+        Ok(hash)
+    }
+
+    fn mine(&mut self, transactions: Vec<H256>, timestamp: u64) {
+        let number = self.blocks.len() as u64;
+        let gas_used = transactions
+            .iter()
+            .filter_map(|hash| self.transactions[hash].1.gas_used)
+            .fold(U256::zero(), |a, b| a + b);
+        self.blocks.push(Block {
+            hash: Some(block_hash(number)),
+            parent_hash: self.blocks.last().and_then(|b| b.hash).unwrap_or_default(),
+            number: Some(number.into()),
+            gas_used,
+            gas_limit: BLOCK_GAS_LIMIT.into(),
+            timestamp: timestamp.into(),
+            transactions,
+            ..Default::default()
+        });
+    }
+}
+
+/// Records the addresses and storage slots which a transaction touches, as
+/// eth_createAccessList does. Like geth, the sender, recipient and
+/// precompiles are left out as they are always warm, except when listing
+/// the slots of the recipient.
+struct AccessListTracer {
+    excluded: Vec<Address>,
+    touched: BTreeMap<Address, BTreeSet<H256>>,
+}
+
+impl AccessListTracer {
+    fn new(message: &Message) -> Self {
+        // The precompiles of Cancun are 0x01 to 0x0a
+        let mut excluded: Vec<Address> = (1..=10u64).map(Address::from_low_u64_be).collect();
+        excluded.push(message.caller);
+        excluded.extend(message.to);
+        Self {
+            excluded,
+            touched: BTreeMap::new(),
+        }
+    }
+
+    fn touch(&mut self, address: Address) {
+        if !self.excluded.contains(&address) {
+            self.touched.entry(address).or_default();
+        }
+    }
+
+    fn access_list(self) -> AccessList {
+        self.touched
+            .into_iter()
+            .map(|(address, keys)| AccessListItem {
+                address,
+                storage_keys: keys.into_iter().collect(),
+            })
+            .collect()
+    }
+}
+
+impl<DB: Database> Inspector<DB> for AccessListTracer {
+    fn step(&mut self, interp: &mut Interpreter, _context: &mut EvmContext<DB>) {
+        let stack = interp.stack();
+        let address_at = |index| {
+            let word = stack.peek(index).ok()?;
+            Some(Address::from_slice(&word.to_be_bytes::<32>()[12..]))
+        };
+        match interp.current_opcode() {
+            opcode::SLOAD | opcode::SSTORE => {
+                if let Ok(slot) = stack.peek(0) {
+                    self.touched
+                        .entry(h160(interp.contract.target_address))
+                        .or_default()
+                        .insert(H256(slot.to_be_bytes()));
+                }
+            }
+            opcode::BALANCE
+            | opcode::EXTCODESIZE
+            | opcode::EXTCODECOPY
+            | opcode::EXTCODEHASH
+            | opcode::SELFDESTRUCT => {
+                if let Some(address) = address_at(0) {
+                    self.touch(address);
+                }
+            }
+            opcode::CALL | opcode::CALLCODE | opcode::DELEGATECALL | opcode::STATICCALL => {
+                if let Some(address) = address_at(1) {
+                    self.touch(address);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Block hashes match those the EVM gives for the BLOCKHASH opcode, which
+/// comes from the empty database underlying the state.
+fn block_hash(number: u64) -> H256 {
+    H256(keccak256(number.to_string().as_bytes()))
+}
+
+fn message(request: CallRequest) -> Message {
+    Message {
+        caller: request.from.unwrap_or_default(),
+        to: request.to,
+        value: request.value.unwrap_or_default(),
+        data: request.data.map(|data| data.0).unwrap_or_default(),
+        gas: request
+            .gas
+            .map_or(BLOCK_GAS_LIMIT, |gas| gas.low_u64().min(BLOCK_GAS_LIMIT)),
+        gas_price: request.gas_price.unwrap_or_default(),
+        gas_priority_fee: None,
+        nonce: None,
+        access_list: request.access_list.unwrap_or_default(),
+    }
+}
+
+/// Decodes a signed legacy, EIP-2930 or EIP-1559 transaction, recovering
+/// the sender. The priority fee of an EIP-1559 transaction is returned with
+/// it, as web3's Transaction has no field for it.
+fn decode_transaction(
+    raw: &[u8],
+    chain_id: u64,
+) -> Result<(Transaction, Option<U256>), rpc::Error> {
+    match raw.first() {
+        Some(0x01) | Some(0x02) => decode_typed(raw, chain_id),
+        Some(0xc0..=0xff) => Ok((decode_legacy(raw, chain_id)?, None)),
+        _ => Err(invalid_params("Unsupported transaction type".to_owned())),
+    }
+}
+
+fn decode_legacy(raw: &[u8], chain_id: u64) -> Result<Transaction, rpc::Error> {
+    let rlp = Rlp::new(raw);
+    if rlp.item_count().map_err(invalid_rlp)? != 9 {
+        return Err(invalid_params("Unsupported transaction type".to_owned()));
+    }
+    let v: u64 = rlp.val_at(6).map_err(invalid_rlp)?;
+    let r: U256 = rlp.val_at(7).map_err(invalid_rlp)?;
+    let s: U256 = rlp.val_at(8).map_err(invalid_rlp)?;
+
+    // See EIP-155 for how the chain id is included in v
+    let (recovery_id, signed_chain_id) = match v {
+        27 | 28 => (v - 27, None),
+        v if v >= 35 => ((v - 35) % 2, Some((v - 35) / 2)),
+        _ => return Err(invalid_params("Invalid signature".to_owned())),
+    };
+    if signed_chain_id.is_some_and(|id| id != chain_id) {
+        return Err(invalid_params(
+            "Transaction is for another chain".to_owned(),
+        ));
+    }
+
+    let mut unsigned = rlp::RlpStream::new();
+    unsigned.begin_list(if signed_chain_id.is_some() { 9 } else { 6 });
+    for i in 0..6 {
+        unsigned.append_raw(rlp.at(i).map_err(invalid_rlp)?.as_raw(), 1);
+    }
+    if let Some(chain_id) = signed_chain_id {
+        unsigned.append(&chain_id);
+        unsigned.append(&0u8);
+        unsigned.append(&0u8);
+    }
+    let from = sender(unsigned.as_raw(), r, s, recovery_id)?;
+
+    Ok(Transaction {
+        hash: H256(keccak256(raw)),
+        nonce: rlp.val_at(0).map_err(invalid_rlp)?,
+        from: Some(from),
+        to: to_at(&rlp, 3)?,
+        value: rlp.val_at(4).map_err(invalid_rlp)?,
+        gas_price: rlp.val_at(1).map_err(invalid_rlp)?,
+        gas: rlp.val_at(2).map_err(invalid_rlp)?,
+        input: Bytes(rlp.val_at(5).map_err(invalid_rlp)?),
+        v: Some(v.into()),
+        r: Some(r),
+        s: Some(s),
+        raw: Some(Bytes(raw.to_vec())),
+        ..Default::default()
+    })
+}
+
+/// Decodes a typed transaction, which is the type followed by the RLP of
+/// [chain_id, nonce, gas_price, gas, to, value, data, access_list,
+/// y_parity, r, s] for type 1 (see EIP-2930). Type 2 has
+/// max_priority_fee_per_gas and max_fee_per_gas in place of gas_price (see
+/// EIP-1559).
+fn decode_typed(raw: &[u8], chain_id: u64) -> Result<(Transaction, Option<U256>), rpc::Error> {
+    let transaction_type = raw[0];
+    // The index of each field after the fees
+    let i = |index: usize| index + transaction_type as usize - 1;
+    let rlp = Rlp::new(&raw[1..]);
+    if rlp.item_count().map_err(invalid_rlp)? != i(11) {
+        return Err(invalid_params("Invalid transaction".to_owned()));
+    }
+    let signed_chain_id: u64 = rlp.val_at(0).map_err(invalid_rlp)?;
+    if signed_chain_id != chain_id {
+        return Err(invalid_params(
+            "Transaction is for another chain".to_owned(),
+        ));
+    }
+    let access_list = rlp
+        .at(i(7))
+        .map_err(invalid_rlp)?
+        .iter()
+        .map(|item| {
+            Ok(AccessListItem {
+                address: item.val_at(0)?,
+                storage_keys: item.list_at(1)?,
+            })
+        })
+        .collect::<Result<AccessList, rlp::DecoderError>>()
+        .map_err(invalid_rlp)?;
+    let y_parity: u64 = rlp.val_at(i(8)).map_err(invalid_rlp)?;
+    let r: U256 = rlp.val_at(i(9)).map_err(invalid_rlp)?;
+    let s: U256 = rlp.val_at(i(10)).map_err(invalid_rlp)?;
+
+    let mut unsigned = rlp::RlpStream::new();
+    unsigned.begin_list(i(8));
+    for index in 0..i(8) {
+        unsigned.append_raw(rlp.at(index).map_err(invalid_rlp)?.as_raw(), 1);
+    }
+    let unsigned: Vec<u8> = [&[transaction_type][..], unsigned.as_raw()].concat();
+    let from = sender(&unsigned, r, s, y_parity)?;
+
+    let tx = Transaction {
+        hash: H256(keccak256(raw)),
+        nonce: rlp.val_at(1).map_err(invalid_rlp)?,
+        from: Some(from),
+        to: to_at(&rlp, i(4))?,
+        value: rlp.val_at(i(5)).map_err(invalid_rlp)?,
+        // The max fee, for type 2
+        gas_price: rlp.val_at(i(2)).map_err(invalid_rlp)?,
+        gas: rlp.val_at(i(3)).map_err(invalid_rlp)?,
+        input: Bytes(rlp.val_at(i(6)).map_err(invalid_rlp)?),
+        v: Some(y_parity.into()),
+        r: Some(r),
+        s: Some(s),
+        raw: Some(Bytes(raw.to_vec())),
+        transaction_type: Some(transaction_type.into()),
+        access_list: Some(access_list),
+        ..Default::default()
+    };
+    let gas_priority_fee = match transaction_type {
+        2 => Some(rlp.val_at(2).map_err(invalid_rlp)?),
+        _ => None,
+    };
+    Ok((tx, gas_priority_fee))
+}
+
+/// The recipient, which is empty for contract creation
+fn to_at(rlp: &Rlp, index: usize) -> Result<Option<Address>, rpc::Error> {
+    let to = rlp.at(index).map_err(invalid_rlp)?;
+    if to.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(to.as_val().map_err(invalid_rlp)?))
+    }
+}
+
+/// Recovers the address which signed the RLP of an unsigned transaction
+fn sender(unsigned: &[u8], r: U256, s: U256, recovery_id: u64) -> Result<Address, rpc::Error> {
+    let mut signature = [0u8; 64];
+    r.to_big_endian(&mut signature[..32]);
+    s.to_big_endian(&mut signature[32..]);
+    recover(&keccak256(unsigned), &signature, recovery_id as i32)
+        .map_err(|_| invalid_params("Invalid signature".to_owned()))
+}
+
+fn invalid_rlp(e: rlp::DecoderError) -> rpc::Error {
+    invalid_params(format!("Invalid transaction: {}", e))
+}
+
+/// The error for a call which did not succeed. Reverts have the same form
+/// as those from geth, with the revert data included.
+fn failure(result: ExecutionResult) -> rpc::Error {
+    match result {
+        ExecutionResult::Revert { output, .. } => {
+            // Error(string)
+            let reason = match output.strip_prefix(&[0x08, 0xc3, 0x79, 0xa0][..]) {
+                Some(data) => match ethabi::decode(&[ethabi::ParamType::String], data) {
+                    Ok(mut tokens) => tokens.pop().and_then(|t| t.into_string()),
+                    Err(_) => None,
+                },
+                None => None,
+            };
+            rpc::Error {
+                code: rpc::ErrorCode::ServerError(3),
+                message: match reason {
+                    Some(reason) => format!("execution reverted: {}", reason),
+                    None => "execution reverted".to_owned(),
+                },
+                data: Some(helpers::serialize(&Bytes(output.to_vec()))),
+            }
+        }
+        ExecutionResult::Halt { reason, .. } => server_error(format!("{:?}", reason)),
+        ExecutionResult::Success { .. } => server_error("Unexpected success".to_owned()),
+    }
+}
+
+fn param<T: DeserializeOwned>(params: &[rpc::Value], index: usize) -> Result<T, rpc::Error> {
+    let value = params.get(index).cloned().unwrap_or(rpc::Value::Null);
+    serde_json::from_value(value).map_err(|e| invalid_params(e.to_string()))
+}
+
+fn invalid_params(message: String) -> rpc::Error {
+    rpc::Error {
+        code: rpc::ErrorCode::InvalidParams,
+        message,
+        data: None,
+    }
+}
+
+fn server_error(message: String) -> rpc::Error {
+    rpc::Error {
+        code: rpc::ErrorCode::ServerError(-32000),
+        message,
+        data: None,
+    }
+}
+
+fn revm_address(address: Address) -> revm::primitives::Address {
+    address.0.into()
+}
+
+fn h160(address: revm::primitives::Address) -> Address {
+    Address::from(address.into_array())
+}
+
+fn word(value: U256) -> Word {
+    let mut bytes = [0u8; 32];
+    value.to_big_endian(&mut bytes);
+    Word::from_be_bytes(bytes)
+}
+
+fn u256(value: Word) -> U256 {
+    U256::from_big_endian(&value.to_be_bytes::<32>())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::secret_key;
+    use crate::{CallProvider, Context, SendProvider};
+
+    /// Creation code for a contract which returns 42 from every call
+    const ANSWER: &str = "600a600c600039600a6000f3602a60005260206000f3";
+
+    const ANSWER_ABI: &str = r#"[{
+        "type": "function",
+        "name": "answer",
+        "stateMutability": "view",
+        "inputs": [],
+        "outputs": [{ "name": "", "type": "uint256" }]
+    }]"#;
+
+    fn answer_abi() -> Arc<ethabi::Contract> {
+        Arc::new(ethabi::Contract::load(ANSWER_ABI.as_bytes()).unwrap())
+    }
+
+    /// Creation code for a contract which reads the code size of
+    /// 0x1111111111111111111111111111111111111111 on every call
+    const EXTCODESIZE: &str = concat!(
+        "6018600c60003960186000f3",
+        "731111111111111111111111111111111111111111",
+        "3b5000"
+    );
+
+    /// A chain on which the account of the context has funds, and has
+    /// deployed ANSWER at the returned address
+    fn deployed() -> (Evm, Web3Context, Address) {
+        let evm = Evm::new();
+        let secret_key = secret_key();
+        let context = evm.context(&secret_key);
+        evm.fund(context.from(), U256::exp10(18));
+        let address = evm
+            .deploy(context.from(), hex::decode(ANSWER).unwrap())
+            .unwrap();
+        (evm, context, address)
+    }
+
+    #[test]
+    pub fn deploys_calls_and_sends() {
+        let (evm, context, address) = deployed();
+        assert_eq!(evm.block_number(), 1);
+
+        let provider = context.provider(address, answer_abi());
+        futures::executor::block_on(async {
+            let answer: U256 = provider
+                .call("answer", (), Default::default())
+                .await
+                .unwrap();
+            assert_eq!(answer, 42.into());
+
+            let options = crate::SendOptions::with(|o| o.value = Some(1000.into()));
+            let receipt = provider
+                .send_data(Vec::new(), Some(options), None)
+                .await
+                .unwrap()
+                .confirmed()
+                .await
+                .unwrap();
+            assert_eq!(receipt.status, Some(1.into()));
+            assert_eq!(receipt.block_number, Some(2.into()));
+        });
+        assert_eq!(evm.balance(address), 1000.into());
+    }
+
+    /// Calls where there is no contract return no data, as do calls at a
+    /// block before the contract was deployed
+    #[test]
+    pub fn calls_without_code_fail() {
+        let (_evm, context, _) = deployed();
+        let provider = context.provider(Address::repeat_byte(7), answer_abi());
+        let result: Result<U256, _> =
+            futures::executor::block_on(provider.call("answer", (), Default::default()));
+        assert!(matches!(result, Err(web3::Error::InvalidResponse(_))));
+    }
+
+    #[test]
+    pub fn sends_from_other_accounts() {
+        let (evm, context, address) = deployed();
+        let other = crate::SafeSecretKey::from_bytes(&[2; 32]).unwrap();
+        let other_address = crate::Signer::address(&other);
+        let context = context.add_signer(other);
+        evm.fund(other_address, U256::exp10(18));
+        assert_eq!(
+            context.accounts().collect::<BTreeSet<_>>(),
+            vec![context.from(), other_address].into_iter().collect()
+        );
+
+        let provider = context.provider(address, answer_abi());
+        futures::executor::block_on(async {
+            let options = crate::SendOptions::with(|o| o.from = Some(other_address));
+            let receipt = provider
+                .send_data(Vec::new(), Some(options), None)
+                .await
+                .unwrap()
+                .confirmed()
+                .await
+                .unwrap();
+            assert_eq!(receipt.status, Some(1.into()));
+            let eth = context.eth();
+            let nonce = eth.transaction_count(other_address, None).await.unwrap();
+            assert_eq!(nonce, 1.into());
+
+            let options = crate::SendOptions::with(|o| o.from = Some(Address::repeat_byte(9)));
+            assert!(provider
+                .send_data(Vec::new(), Some(options), None)
+                .await
+                .is_err());
+        });
+    }
+
+    #[test]
+    pub fn sends_eip1559_transactions() {
+        let (_evm, context, address) = deployed();
+        let context = context.with_fee_model(crate::FeeModel::Eip1559(Default::default()));
+
+        let provider = context.provider(address, answer_abi());
+        futures::executor::block_on(async {
+            let receipt = provider
+                .send_data(Vec::new(), None, None)
+                .await
+                .unwrap()
+                .confirmed()
+                .await
+                .unwrap();
+            assert_eq!(receipt.status, Some(1.into()));
+            assert_eq!(receipt.transaction_type, Some(2.into()));
+
+            // A gas price alone sends a legacy transaction
+            let options = crate::SendOptions::with(|o| o.gas_price = Some(GAS_PRICE.into()));
+            let receipt = provider
+                .send_data(Vec::new(), Some(options), None)
+                .await
+                .unwrap()
+                .confirmed()
+                .await
+                .unwrap();
+            assert_eq!(receipt.transaction_type, None);
+        });
+    }
+
+    #[test]
+    pub fn creates_access_lists() {
+        let (evm, context, answer) = deployed();
+        let address = evm
+            .deploy(context.from(), hex::decode(EXTCODESIZE).unwrap())
+            .unwrap();
+
+        let provider = context.provider(address, answer_abi());
+        futures::executor::block_on(async {
+            let report = provider
+                .create_access_list(Vec::new(), &Default::default())
+                .await
+                .unwrap();
+            let touched = Address::repeat_byte(0x11);
+            assert_eq!(
+                report.access_list,
+                vec![AccessListItem {
+                    address: touched,
+                    storage_keys: Vec::new(),
+                }]
+            );
+            assert!(report.reduces_gas());
+
+            let options = crate::SendOptions::with(|o| o.create_access_list = true);
+            let receipt = provider
+                .send_data(Vec::new(), Some(options.clone()), None)
+                .await
+                .unwrap()
+                .confirmed()
+                .await
+                .unwrap();
+            assert_eq!(receipt.status, Some(1.into()));
+            assert_eq!(receipt.transaction_type, Some(1.into()));
+
+            // Nothing is touched, so there is nothing to gain
+            let provider = context.provider(answer, answer_abi());
+            let report = provider
+                .create_access_list(Vec::new(), &options)
+                .await
+                .unwrap();
+            assert!(report.access_list.is_empty());
+            assert!(!report.reduces_gas());
+            let receipt = provider
+                .send_data(Vec::new(), Some(options), None)
+                .await
+                .unwrap()
+                .confirmed()
+                .await
+                .unwrap();
+            assert_eq!(receipt.transaction_type, None);
+        });
+    }
+
+    #[test]
+    pub fn attaches_pending_transactions() {
+        let (evm, context, address) = deployed();
+
+        let provider = context.provider(address, answer_abi());
+        futures::executor::block_on(async {
+            let pending = provider.send_data(Vec::new(), None, Some(1)).await.unwrap();
+            let saved = serde_json::to_string(pending.sent()).unwrap();
+            drop(pending);
+
+            let sent: crate::SentTransaction = serde_json::from_str(&saved).unwrap();
+            assert_eq!(sent.from, context.from());
+            assert_eq!(sent.confirmations, 1);
+            let pending = crate::PendingTransaction::attach(&context, sent);
+            let receipt = pending.included().await.unwrap();
+            assert_eq!(receipt.transaction_hash, pending.hash());
+            assert_eq!(receipt.block_number, Some(2.into()));
+
+            evm.mine();
+            assert_eq!(pending.confirmed().await.unwrap(), receipt);
+            assert_eq!(pending.finalized().await.unwrap(), receipt);
+        });
+    }
+}
@@ -0,0 +1,147 @@
+use crate::BoxTransport;
+use serde::Deserialize;
+use web3::api::{Eth, Namespace as _};
+use web3::helpers;
+use web3::types::U256;
+use web3::Transport as _;
+
+/// Which type of transaction a context sends, and so how fees are paid.
+/// This is per context because it depends on the chain, as not all chains
+/// support EIP-1559.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum FeeModel {
+    /// Legacy transactions, priced by eth_gasPrice unless given a price
+    #[default]
+    Legacy,
+    /// EIP-1559 transactions, with fees estimated from recent blocks
+    /// unless given
+    Eip1559(FeeEstimator),
+}
+
+/// Estimates EIP-1559 fees from the priority fees paid in recent blocks,
+/// using eth_feeHistory.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FeeEstimator {
+    /// How many recent blocks to consider
+    pub blocks: u64,
+    /// Which percentile of the priority fees paid in each block to use.
+    /// Higher percentiles outbid more transactions, so are included sooner.
+    pub percentile: f64,
+    /// The max fee is the base fee times this, plus the priority fee. The
+    /// base fee can rise by 12.5% per block, so the default of 2 covers at
+    /// least 6 full blocks in a row.
+    pub base_fee_multiplier: u64,
+    /// The least priority fee to offer, for when recent blocks paid none
+    pub min_priority_fee: U256,
+}
+
+impl FeeEstimator {
+    /// Pays less than most recent transactions, so may wait a while
+    pub fn slow() -> Self {
+        Self {
+            percentile: 10.0,
+            ..Default::default()
+        }
+    }
+
+    /// Pays what the median recent transaction paid
+    pub fn standard() -> Self {
+        Self::default()
+    }
+
+    /// Pays more than most recent transactions
+    pub fn fast() -> Self {
+        Self {
+            percentile: 90.0,
+            base_fee_multiplier: 3,
+            ..Default::default()
+        }
+    }
+
+    /// Estimates (max_fee_per_gas, max_priority_fee_per_gas) for the next
+    /// block.
+    pub(crate) async fn estimate(&self, eth: &Eth<BoxTransport>) -> web3::Result<(U256, U256)> {
+        let history = eth
+            .transport()
+            .execute(
+                "eth_feeHistory",
+                vec![
+                    helpers::serialize(&U256::from(self.blocks)),
+                    helpers::serialize(&"latest"),
+                    helpers::serialize(&[self.percentile]),
+                ],
+            )
+            .await?;
+        let history: FeeHistory = serde_json::from_value(history)
+            .map_err(|e| web3::Error::Decoder(format!("{:?}", e)))?;
+        Ok(self.fees(&history))
+    }
+
+    fn fees(&self, history: &FeeHistory) -> (U256, U256) {
+        // The last entry is the base fee of the next block
+        let base_fee = history.base_fee_per_gas.last().copied().unwrap_or_default();
+        let mut rewards: Vec<U256> = history
+            .reward
+            .iter()
+            .filter_map(|rewards| rewards.first().copied())
+            .collect();
+        rewards.sort();
+        let priority_fee = rewards
+            .get(rewards.len() / 2)
+            .copied()
+            .unwrap_or_default()
+            .max(self.min_priority_fee);
+        let max_fee = base_fee * self.base_fee_multiplier + priority_fee;
+        (max_fee, priority_fee)
+    }
+}
+
+impl Default for FeeEstimator {
+    fn default() -> Self {
+        Self {
+            blocks: 10,
+            percentile: 50.0,
+            base_fee_multiplier: 2,
+            // 0.1 gwei
+            min_priority_fee: U256::from(100_000_000),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct FeeHistory {
+    base_fee_per_gas: Vec<U256>,
+    /// The priority fee at each requested percentile, for each block
+    #[serde(default)]
+    reward: Vec<Vec<U256>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    pub fn estimates_from_history() {
+        let history: FeeHistory = serde_json::from_value(json!({
+            "oldestBlock": "0x10",
+            "baseFeePerGas": ["0x64", "0x6e", "0x78"],
+            "gasUsedRatio": [0.5, 0.9],
+            "reward": [["0x5"], ["0x3"], ["0x9"]]
+        }))
+        .unwrap();
+        let estimator = FeeEstimator {
+            min_priority_fee: 1.into(),
+            ..Default::default()
+        };
+        // The median reward, and twice the next base fee
+        assert_eq!(estimator.fees(&history), (245.into(), 5.into()));
+
+        let estimator = FeeEstimator {
+            min_priority_fee: 10.into(),
+            ..Default::default()
+        };
+        assert_eq!(estimator.fees(&history), (250.into(), 10.into()));
+    }
+}
@@ -0,0 +1,511 @@
+use crate::secrets::SafeBytes;
+use crate::SafeSecretKey;
+use aes::cipher::{KeyIvInit as _, StreamCipher as _};
+use salsa20::cipher::{typenum::U4, StreamCipherCore as _};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sha2::Sha256;
+use std::convert::TryInto as _;
+use std::fmt;
+use subtle::ConstantTimeEq as _;
+use web3::signing::{keccak256, Key as _, SecretKeyRef};
+use zeroize::Zeroizing;
+
+/// How the key which encrypts a keystore is derived from its password.
+/// Both are deliberately slow, to make guessing the password expensive.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Kdf {
+    Scrypt { n: u64, r: u32, p: u32 },
+    Pbkdf2 { iterations: u32 },
+}
+
+impl Kdf {
+    /// The parameters geth uses with --lightkdf, which take far less time
+    /// and memory than the default
+    pub fn light() -> Self {
+        Kdf::Scrypt {
+            n: 1 << 12,
+            r: 8,
+            p: 6,
+        }
+    }
+}
+
+impl Default for Kdf {
+    /// The parameters geth uses
+    fn default() -> Self {
+        Kdf::Scrypt {
+            n: 1 << 18,
+            r: 8,
+            p: 1,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum KeystoreError {
+    /// The password is wrong, or the keystore has been altered
+    InvalidPassword,
+    /// The keystore is not JSON of the expected form
+    Malformed(String),
+    /// The keystore uses a version, cipher or KDF which isn't supported
+    Unsupported(String),
+    /// The key could not be locked into memory
+    Lock,
+}
+
+impl fmt::Display for KeystoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeystoreError::InvalidPassword => write!(f, "Invalid keystore password"),
+            KeystoreError::Malformed(reason) => write!(f, "Malformed keystore: {}", reason),
+            KeystoreError::Unsupported(what) => write!(f, "Unsupported keystore {}", what),
+            KeystoreError::Lock => write!(f, "Could not lock the key into memory"),
+        }
+    }
+}
+
+impl std::error::Error for KeystoreError {}
+
+impl SafeSecretKey {
+    /// Decrypts a V3 keystore (as written by geth and most wallets)
+    /// straight into locked memory. Scrypt parameters which need more than
+    /// 1GiB of memory are refused, as the keystore may not be trusted.
+    pub fn from_keystore(json: &str, password: impl AsRef<[u8]>) -> Result<Self, KeystoreError> {
+        let keystore: Keystore =
+            serde_json::from_str(json).map_err(|e| KeystoreError::Malformed(e.to_string()))?;
+        if keystore.version != 3 {
+            return Err(KeystoreError::Unsupported(format!(
+                "version {}",
+                keystore.version
+            )));
+        }
+        let crypto = keystore.crypto;
+        if crypto.cipher != "aes-128-ctr" {
+            return Err(KeystoreError::Unsupported(format!(
+                "cipher {}",
+                crypto.cipher
+            )));
+        }
+
+        let derived = crypto.kdf.derive(password.as_ref())?;
+        let expected = mac(&derived, &crypto.ciphertext.0);
+        // In constant time. Slices of different lengths are unequal.
+        if !bool::from(expected.ct_eq(&crypto.mac.0)) {
+            return Err(KeystoreError::InvalidPassword);
+        }
+        if crypto.ciphertext.0.len() != 32 {
+            return Err(KeystoreError::Malformed(
+                "the ciphertext is not 32 bytes".to_owned(),
+            ));
+        }
+        // Decrypted in place, so the key is only ever in locked memory
+        let mut plain = SafeBytes::new(32).map_err(|()| KeystoreError::Lock)?;
+        plain.copy_from_slice(&crypto.ciphertext.0);
+        aes_128_ctr(&derived, &crypto.cipherparams.iv.0, &mut plain)?;
+        if !SafeSecretKey::is_valid(&plain) {
+            return Err(KeystoreError::Malformed("not a secp256k1 key".to_owned()));
+        }
+        let key = SafeSecretKey::from_bytes(&plain).map_err(|()| KeystoreError::Lock)?;
+
+        if let Some(address) = keystore.address {
+            if address.0 != SecretKeyRef::new(&key).address().as_bytes() {
+                return Err(KeystoreError::Malformed(
+                    "the address is not that of the key".to_owned(),
+                ));
+            }
+        }
+        Ok(key)
+    }
+
+    /// Encrypts the key into a new V3 keystore, with a random salt and IV
+    pub fn to_keystore(
+        &self,
+        password: impl AsRef<[u8]>,
+        kdf: &Kdf,
+    ) -> Result<String, KeystoreError> {
+        let salt = Hex(rand::random::<[u8; 32]>().to_vec());
+        let kdf = match *kdf {
+            Kdf::Scrypt { n, r, p } => KdfParams::Scrypt {
+                dklen: 32,
+                n,
+                r,
+                p,
+                salt,
+            },
+            Kdf::Pbkdf2 { iterations } => KdfParams::Pbkdf2 {
+                dklen: 32,
+                c: iterations,
+                prf: "hmac-sha256".to_owned(),
+                salt,
+            },
+        };
+        let derived = kdf.derive(password.as_ref())?;
+        let iv = rand::random::<[u8; 16]>();
+        // Encrypted in place, so the key is only ever in locked memory
+        let mut ciphertext = SafeBytes::new(32).map_err(|()| KeystoreError::Lock)?;
+        ciphertext.copy_from_slice(self.as_bytes());
+        aes_128_ctr(&derived, &iv, &mut ciphertext)?;
+        let keystore = Keystore {
+            crypto: Crypto {
+                cipher: "aes-128-ctr".to_owned(),
+                cipherparams: CipherParams {
+                    iv: Hex(iv.to_vec()),
+                },
+                mac: Hex(mac(&derived, &ciphertext).to_vec()),
+                ciphertext: Hex(ciphertext.to_vec()),
+                kdf,
+            },
+            id: uuid(),
+            version: 3,
+            address: Some(Hex(SecretKeyRef::new(self).address().as_bytes().to_vec())),
+        };
+        Ok(serde_json::to_string(&keystore).expect("keystores serialize; qed"))
+    }
+}
+
+/// The MAC is of the second half of the derived key and the ciphertext
+fn mac(derived: &[u8], ciphertext: &[u8]) -> [u8; 32] {
+    let mut input = Zeroizing::new(Vec::with_capacity(16 + ciphertext.len()));
+    input.extend_from_slice(&derived[16..32]);
+    input.extend_from_slice(ciphertext);
+    keccak256(&input)
+}
+
+/// Encrypts or decrypts (which are the same in CTR mode) in place with the
+/// first half of the derived key
+fn aes_128_ctr(derived: &[u8], iv: &[u8], data: &mut [u8]) -> Result<(), KeystoreError> {
+    type Aes128Ctr = ctr::Ctr128BE<aes::Aes128>;
+    let mut cipher = Aes128Ctr::new_from_slices(&derived[..16], iv)
+        .map_err(|_| KeystoreError::Malformed("the IV is not 16 bytes".to_owned()))?;
+    cipher.apply_keystream(data);
+    Ok(())
+}
+
+/// A random (version 4) UUID
+fn uuid() -> String {
+    let mut bytes = rand::random::<[u8; 16]>();
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex = hex::encode(bytes);
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+#[derive(Serialize, Deserialize)]
+struct Keystore {
+    // Some wallets capitalize this
+    #[serde(alias = "Crypto")]
+    crypto: Crypto,
+    id: String,
+    version: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    address: Option<Hex>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Crypto {
+    cipher: String,
+    cipherparams: CipherParams,
+    ciphertext: Hex,
+    #[serde(flatten)]
+    kdf: KdfParams,
+    mac: Hex,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CipherParams {
+    iv: Hex,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "kdf", content = "kdfparams", rename_all = "lowercase")]
+enum KdfParams {
+    Scrypt {
+        dklen: usize,
+        n: u64,
+        r: u32,
+        p: u32,
+        salt: Hex,
+    },
+    Pbkdf2 {
+        c: u32,
+        dklen: usize,
+        prf: String,
+        salt: Hex,
+    },
+}
+
+impl KdfParams {
+    fn derive(&self, password: &[u8]) -> Result<SafeBytes, KeystoreError> {
+        let dklen = match self {
+            KdfParams::Scrypt { dklen, .. } | KdfParams::Pbkdf2 { dklen, .. } => *dklen,
+        };
+        // Half is the AES key and the other half is for the MAC
+        if dklen != 32 {
+            return Err(KeystoreError::Unsupported(format!("dklen {}", dklen)));
+        }
+        let mut derived = SafeBytes::new(dklen).map_err(|()| KeystoreError::Lock)?;
+        match self {
+            KdfParams::Scrypt { n, r, p, salt, .. } => {
+                scrypt(password, &salt.0, *n, *r, *p, &mut derived)?;
+            }
+            KdfParams::Pbkdf2 { c, prf, salt, .. } => {
+                if prf != "hmac-sha256" {
+                    return Err(KeystoreError::Unsupported(format!("prf {}", prf)));
+                }
+                pbkdf2::pbkdf2_hmac::<Sha256>(password, &salt.0, *c, &mut derived);
+            }
+        }
+        Ok(derived)
+    }
+}
+
+/// The most memory which the scrypt parameters of a keystore may need. This
+/// is 4 times what geth's default parameters need.
+const MAX_SCRYPT_MEMORY: u64 = 1 << 30;
+
+/// Scrypt, with the scrypt crate where it can. That crate refuses
+/// n >= 2^(16 * r), as RFC 7914 says to, but the keystores of some wallets,
+/// including the scrypt test vector of the Web3 Secret Storage Definition
+/// (n = 2^18, r = 1), don't meet that bound. The crate doesn't expose its
+/// ROMix, so those are derived with the ROMix below.
+fn scrypt(
+    password: &[u8],
+    salt: &[u8],
+    n: u64,
+    r: u32,
+    p: u32,
+    output: &mut [u8],
+) -> Result<(), KeystoreError> {
+    let unsupported =
+        || KeystoreError::Unsupported(format!("scrypt parameters n={} r={} p={}", n, r, p));
+    if n < 2 || !n.is_power_of_two() || r == 0 || p == 0 {
+        return Err(unsupported());
+    }
+    // The parameters come from the keystore, which could otherwise have
+    // this allocate any amount of memory
+    let block = 128 * r as u64;
+    let memory = n
+        .checked_add(p as u64 + 1)
+        .and_then(|blocks| blocks.checked_mul(block))
+        .filter(|&memory| memory <= MAX_SCRYPT_MEMORY)
+        .ok_or_else(unsupported)?;
+    let log_n = n.trailing_zeros();
+
+    if log_n < 16 * r {
+        let params =
+            scrypt::Params::new(log_n as u8, r, p, output.len()).map_err(|_| unsupported())?;
+        return scrypt::scrypt(password, salt, &params, output).map_err(|_| unsupported());
+    }
+
+    let (n, block) = (n as usize, block as usize);
+    let mut b = Zeroizing::new(vec![0; block * p as usize]);
+    pbkdf2::pbkdf2_hmac::<Sha256>(password, salt, 1, &mut b);
+    let mut v = Zeroizing::new(Vec::new());
+    v.try_reserve_exact(memory as usize - b.len())
+        .map_err(|_| unsupported())?;
+    v.resize(block * n, 0);
+    let mut t = Zeroizing::new(vec![0; block]);
+    for chunk in b.chunks_mut(block) {
+        ro_mix(chunk, &mut v, &mut t, n);
+    }
+    pbkdf2::pbkdf2_hmac::<Sha256>(password, &b, 1, output);
+    Ok(())
+}
+
+/// The ROMix of scrypt, in place, as the scrypt crate has it. `v` holds n
+/// blocks and `t` one block.
+fn ro_mix(b: &mut [u8], v: &mut [u8], t: &mut [u8], n: usize) {
+    let len = b.len();
+    for chunk in v.chunks_mut(len) {
+        chunk.copy_from_slice(b);
+        block_mix(chunk, b);
+    }
+    for _ in 0..n {
+        // Integerify, mod n as n is a power of 2
+        let last = &b[len - 64..len - 60];
+        let j = u32::from_le_bytes(last.try_into().unwrap()) as usize & (n - 1);
+        xor(b, &v[j * len..(j + 1) * len], t);
+        block_mix(t, b);
+    }
+}
+
+/// The BlockMix of scrypt, with Salsa20/8 as the hash. The buffers here are
+/// zeroized, as is the state of the Salsa20 core when it is dropped, but
+/// copies of that state made by moving it into the core may be left on the
+/// stack.
+fn block_mix(input: &[u8], output: &mut [u8]) {
+    type Salsa20_8 = salsa20::SalsaCore<U4>;
+    let mut x = Zeroizing::new([0u8; 64]);
+    x.copy_from_slice(&input[input.len() - 64..]);
+    let mut t = Zeroizing::new([0u8; 64]);
+    for (i, chunk) in input.chunks(64).enumerate() {
+        xor(&*x, chunk, &mut *t);
+        let mut state = Zeroizing::new([0u32; 16]);
+        for (word, bytes) in state.iter_mut().zip(t.chunks_exact(4)) {
+            *word = u32::from_le_bytes(bytes.try_into().unwrap());
+        }
+        Salsa20_8::from_raw_state(*state).write_keystream_block((&mut *x).into());
+        // Even blocks go to the first half of the output and odd to the second
+        let pos = (i / 2) * 64 + (i % 2) * (input.len() / 2);
+        output[pos..pos + 64].copy_from_slice(&*x);
+    }
+}
+
+fn xor(x: &[u8], y: &[u8], output: &mut [u8]) {
+    for ((out, x), y) in output.iter_mut().zip(x).zip(y) {
+        *out = x ^ y;
+    }
+}
+
+/// Bytes as hex without a 0x prefix, as keystores have them
+struct Hex(Vec<u8>);
+
+impl Serialize for Hex {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&hex::encode(&self.0))
+    }
+}
+
+impl<'de> Deserialize<'de> for Hex {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        let s = s.strip_prefix("0x").unwrap_or(&s);
+        hex::decode(s).map(Hex).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The test vectors of the Web3 Secret Storage Definition
+    const PBKDF2: &str = r#"{
+        "crypto": {
+            "cipher": "aes-128-ctr",
+            "cipherparams": {"iv": "6087dab2f9fdbbfaddc31a909735c1e6"},
+            "ciphertext": "5318b4d5bcd28de64ee5559e671353e16f075ecae9f99c7a79a38af5f869aa46",
+            "kdf": "pbkdf2",
+            "kdfparams": {
+                "c": 262144,
+                "dklen": 32,
+                "prf": "hmac-sha256",
+                "salt": "ae3cd4e7013836a3df6bd7241b12db061dbe2c6785853cce422d148a624ce0bd"
+            },
+            "mac": "517ead924a9d0dc3124507e3393d175ce3ff7c1e96529c6c555ce9e51205e9b2"
+        },
+        "id": "3198bc9c-6672-5ab3-d995-4942343ae5b6",
+        "version": 3
+    }"#;
+
+    const SCRYPT: &str = r#"{
+        "crypto": {
+            "cipher": "aes-128-ctr",
+            "cipherparams": {"iv": "83dbcc02d8ccb40e466191a123791e0e"},
+            "ciphertext": "d172bf743a674da9cdad04534d56926ef8358534d458fffccd4e6ad2fbde479c",
+            "kdf": "scrypt",
+            "kdfparams": {
+                "dklen": 32,
+                "n": 262144,
+                "p": 8,
+                "r": 1,
+                "salt": "ab0c7876052600dd703518d6fc3fe8984592145b591fc8fb5c6d43190334ba19"
+            },
+            "mac": "2103ac29920d71da29f15d75b4a16dbe95cfd7ff8faea1056c33131d846e3097"
+        },
+        "id": "3198bc9c-6672-5ab3-d995-4942343ae5b6",
+        "version": 3
+    }"#;
+
+    const KEY: &str = "7a28b5ba57c53603b0b07b56bba752f7784bf506fa95edc395f5cf6c7514fe9d";
+
+    /// These take about a minute without optimization, so run them with
+    /// `cargo test --release -- --ignored`
+    #[test]
+    #[ignore]
+    pub fn decrypts_test_vectors() {
+        for json in &[PBKDF2, SCRYPT] {
+            let key = SafeSecretKey::from_keystore(json, "testpassword").unwrap();
+            assert_eq!(hex::encode(key.as_bytes()), KEY);
+        }
+    }
+
+    /// The first test vector of RFC 7914, less the last 32 bytes, which the
+    /// scrypt crate derives
+    #[test]
+    pub fn derives_with_scrypt() {
+        let kdf = KdfParams::Scrypt {
+            dklen: 32,
+            n: 16,
+            r: 1,
+            p: 1,
+            salt: Hex(Vec::new()),
+        };
+        assert_eq!(
+            hex::encode(&*kdf.derive(b"").unwrap()),
+            "77d6576238657b203b19ca42c18a0497f16b4844e3074ae8dfdffa3fede21442"
+        );
+    }
+
+    /// The least n which the scrypt crate refuses for r = 1, as RFC 7914
+    /// says to, so this is derived by ro_mix. The key is from a separate
+    /// implementation of RFC 7914, which gives the RFC's own vectors.
+    #[test]
+    pub fn derives_with_scrypt_beyond_rfc_bound() {
+        let kdf = KdfParams::Scrypt {
+            dklen: 32,
+            n: 1 << 16,
+            r: 1,
+            p: 1,
+            salt: Hex(b"NaCl".to_vec()),
+        };
+        assert_eq!(
+            hex::encode(&*kdf.derive(b"password").unwrap()),
+            "e31d00a86544bc78c07b2cbcb9df8fa5add5e6390e28153509c032382914c0b6"
+        );
+    }
+
+    #[test]
+    pub fn refuses_costly_scrypt() {
+        let kdf = KdfParams::Scrypt {
+            dklen: 32,
+            n: 1 << 40,
+            r: 8,
+            p: 1,
+            salt: Hex(b"NaCl".to_vec()),
+        };
+        assert!(matches!(
+            kdf.derive(b"password"),
+            Err(KeystoreError::Unsupported(_))
+        ));
+    }
+
+    #[test]
+    pub fn round_trips() {
+        let key = SafeSecretKey::from_bytes(&hex::decode(KEY).unwrap()).unwrap();
+        // Far cheaper than any wallet would use, so that this is quick
+        let kdfs = [
+            Kdf::Scrypt {
+                n: 1 << 10,
+                r: 8,
+                p: 1,
+            },
+            Kdf::Pbkdf2 { iterations: 1000 },
+        ];
+        for kdf in &kdfs {
+            let json = key.to_keystore("password", kdf).unwrap();
+            let decrypted = SafeSecretKey::from_keystore(&json, "password").unwrap();
+            assert_eq!(decrypted.as_bytes(), key.as_bytes());
+            assert!(matches!(
+                SafeSecretKey::from_keystore(&json, "wrong"),
+                Err(KeystoreError::InvalidPassword)
+            ));
+        }
+    }
+}
@@ -2,12 +2,57 @@
 #[doc(hidden)]
 pub mod internal;
 
+mod access_list;
+mod block;
 mod context;
+mod eip712;
+#[cfg(feature = "evm")]
+mod evm;
+mod fees;
+mod keystore;
+mod middleware;
+mod mnemonic;
+mod mock;
+mod multicall;
+mod nonce;
+mod options;
+mod pending;
 mod providers;
+mod remote_signer;
+mod replay;
+mod retry;
 mod secrets;
+mod signer;
+#[cfg(test)]
+mod test_support;
+mod transaction;
+mod transports;
 mod web3_provider;
 
 pub use secrets::SafeSecretKey;
+pub use signer::{recover_message, signature_from_bytes, signature_to_bytes, Signer};
+
+pub use access_list::AccessListReport;
+pub use block::BlockRef;
+pub use eip712::{Eip712, Eip712Domain, TypedData, TypedDataField, Types};
+#[cfg(feature = "evm")]
+pub use evm::{Evm, DEFAULT_CHAIN_ID};
+pub use fees::{FeeEstimator, FeeModel};
+pub use keystore::{Kdf, KeystoreError};
+pub use middleware::{Layer, Layered, Logging, LoggingProvider};
+pub use mnemonic::{Mnemonic, MnemonicError};
+pub use mock::{ExpectationBuilder, MockContext, MockProvider, RecordedCall, RecordedSend};
+pub use multicall::{
+    CallHandle, Multicall, MulticallError, MulticallResults, PreparedCall, Reverted,
+    MULTICALL3_ADDRESS,
+};
+pub use options::{CallOptions, SendOptions};
+pub use pending::{EscalationPolicy, PendingTransaction, SentTransaction, TransactionEvent};
+pub use remote_signer::RemoteSigner;
+pub use replay::{Recording, Replay};
+pub use retry::{Retry, RetryPolicy};
+pub use transaction::{Fees, SignedTransaction, Transaction};
+pub use transports::{BatchingHttp, BoxTransport, ReconnectingWebSocket};
 
 pub use providers::{CallProvider, SendProvider};
 pub use web3_provider::Web3Provider;
@@ -0,0 +1,187 @@
+use crate::{CallOptions, CallProvider, Context, SendOptions, SendProvider};
+use async_trait::async_trait;
+use std::fmt;
+use std::sync::Arc;
+use web3::contract::tokens::{Detokenize, Tokenize};
+use web3::types::Address;
+
+/// Middleware for providers. A layer wraps each provider made by a context
+/// in a provider of its own, which implements CallProvider and/or
+/// SendProvider by doing its work and delegating to the inner provider.
+///
+/// Layers are added to a context with `Context::with_layer`, and the
+/// resulting context can be layered again to build up a stack.
+///
+/// ```ignore
+/// let context = Web3Context::new(url, from, &secret_key)?
+///     .with_layer(MyMetrics::new())
+///     .with_layer(Logging);
+/// let token = Token::new(address, &context);
+/// ```
+pub trait Layer<P> {
+    type Provider;
+    fn layer(&self, contract: Address, inner: P) -> Self::Provider;
+}
+
+/// A context whose providers are those of another context, wrapped by a
+/// layer. See `Context::with_layer`.
+#[derive(Clone, Debug)]
+pub struct Layered<C, L> {
+    context: C,
+    layer: L,
+}
+
+impl<C, L> Layered<C, L> {
+    pub fn new(context: C, layer: L) -> Self {
+        Self { context, layer }
+    }
+
+    /// The context being wrapped
+    pub fn inner(&self) -> &C {
+        &self.context
+    }
+}
+
+impl<C, L> Context for Layered<C, L>
+where
+    C: Context,
+    L: Layer<C::Provider>,
+{
+    type Provider = L::Provider;
+    fn provider(&self, contract: Address, abi: Arc<ethabi::Contract>) -> Self::Provider {
+        let inner = self.context.provider(contract, abi);
+        self.layer.layer(contract, inner)
+    }
+}
+
+/// Logs each call and send, and its outcome, with the `log` crate. Calls
+/// are logged at debug level, sends at info, and failures at warn.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Logging;
+
+impl<P> Layer<P> for Logging {
+    type Provider = LoggingProvider<P>;
+    fn layer(&self, contract: Address, inner: P) -> Self::Provider {
+        LoggingProvider { contract, inner }
+    }
+}
+
+/// The provider for the Logging layer
+pub struct LoggingProvider<P> {
+    contract: Address,
+    inner: P,
+}
+
+impl<P> LoggingProvider<P> {
+    fn log<T, E: fmt::Display>(&self, level: log::Level, what: &str, result: &Result<T, E>) {
+        match result {
+            Ok(_) => log::log!(level, "{} on {:?} succeeded", what, self.contract),
+            Err(e) => log::warn!("{} on {:?} failed: {}", what, self.contract, e),
+        }
+    }
+}
+
+#[async_trait]
+impl<P> CallProvider for LoggingProvider<P>
+where
+    P: CallProvider + Sync,
+{
+    async fn call<Out: Detokenize + Unpin + Send, Params: Tokenize + Send>(
+        &self,
+        name: &'static str,
+        params: Params,
+        options: CallOptions,
+    ) -> Result<Out, web3::Error> {
+        let result = self.inner.call(name, params, options).await;
+        self.log(log::Level::Debug, &format!("Call to {}", name), &result);
+        result
+    }
+}
+
+#[async_trait]
+impl<P> SendProvider for LoggingProvider<P>
+where
+    P: SendProvider + Sync,
+{
+    type Out = P::Out;
+
+    async fn send<Params: Tokenize + Send>(
+        &self,
+        func: &'static str,
+        params: Params,
+        options: Option<SendOptions>,
+        confirmations: Option<usize>,
+    ) -> Result<Self::Out, web3::Error> {
+        let result = self.inner.send(func, params, options, confirmations).await;
+        self.log(log::Level::Info, &format!("Send to {}", func), &result);
+        result
+    }
+
+    async fn send_data(
+        &self,
+        data: Vec<u8>,
+        options: Option<SendOptions>,
+        confirmations: Option<usize>,
+    ) -> Result<Self::Out, web3::Error> {
+        let result = self.inner.send_data(data, options, confirmations).await;
+        self.log(log::Level::Info, "Send", &result);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MockContext;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use web3::types::U256;
+
+    /// Counts calls, as a layer from outside the crate would
+    #[derive(Clone, Default)]
+    struct Counting(Arc<AtomicUsize>);
+
+    struct CountingProvider<P>(P, Arc<AtomicUsize>);
+
+    impl<P> Layer<P> for Counting {
+        type Provider = CountingProvider<P>;
+        fn layer(&self, _contract: Address, inner: P) -> Self::Provider {
+            CountingProvider(inner, self.0.clone())
+        }
+    }
+
+    #[async_trait]
+    impl<P: CallProvider + Sync> CallProvider for CountingProvider<P> {
+        async fn call<Out: Detokenize + Unpin + Send, Params: Tokenize + Send>(
+            &self,
+            name: &'static str,
+            params: Params,
+            options: CallOptions,
+        ) -> Result<Out, web3::Error> {
+            self.1.fetch_add(1, Ordering::SeqCst);
+            self.0.call(name, params, options).await
+        }
+    }
+
+    #[test]
+    pub fn stacks_layers() {
+        let mock = MockContext::new();
+        mock.expect("totalSupply").returns(U256::from(5));
+        let counting = Counting::default();
+        let context = mock
+            .clone()
+            .with_layer(Logging)
+            .with_layer(counting.clone());
+
+        let provider = context.provider(Address::zero(), Default::default());
+        futures::executor::block_on(async {
+            let supply: U256 = provider
+                .call("totalSupply", (), Default::default())
+                .await
+                .unwrap();
+            assert_eq!(supply, 5.into());
+        });
+        assert_eq!(counting.0.load(Ordering::SeqCst), 1);
+        assert_eq!(mock.calls().len(), 1);
+    }
+}
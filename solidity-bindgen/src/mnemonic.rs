@@ -0,0 +1,260 @@
+use crate::secrets::SafeBytes;
+use crate::SafeSecretKey;
+use hmac::{Hmac, Mac as _};
+use secp256k1::{PublicKey, Secp256k1};
+use sha2::{Digest as _, Sha256, Sha512};
+use std::fmt;
+use zeroize::{Zeroize as _, Zeroizing};
+
+/// The BIP-44 path of Ethereum accounts, less the index
+const ACCOUNT_PATH: &str = "m/44'/60'/0'/0";
+
+const HARDENED: u32 = 1 << 31;
+
+/// The BIP-39 English wordlist, one word per line
+const ENGLISH: &str = include_str!("mnemonic/english.txt");
+
+/// The seed of a BIP-39 mnemonic phrase, from which keys are derived per
+/// BIP-32. The seed and each key along the way are locked into memory and
+/// zeroized, as SafeSecretKey is.
+///
+/// ```ignore
+/// let mnemonic = Mnemonic::new(&phrase, "")?;
+/// let context = Web3Context::with_signer(transport, mnemonic.account(0)?);
+/// ```
+pub struct Mnemonic {
+    seed: SafeBytes,
+}
+
+impl fmt::Debug for Mnemonic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Mnemonic").finish()
+    }
+}
+
+#[derive(Debug)]
+pub enum MnemonicError {
+    /// A word of the phrase isn't in the English wordlist, there are too
+    /// few or too many words, or the checksum doesn't match
+    InvalidPhrase,
+    /// The derivation path is not of the form m/44'/60'/0'/0/0
+    InvalidPath(String),
+    /// The path derives an invalid key. This has a chance of less than
+    /// 1 in 2^127, so the next index is as good as any other.
+    InvalidKey,
+    /// The secrets could not be locked into memory
+    Lock,
+}
+
+impl fmt::Display for MnemonicError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MnemonicError::InvalidPhrase => write!(f, "Invalid mnemonic phrase"),
+            MnemonicError::InvalidPath(path) => write!(f, "Invalid derivation path {}", path),
+            MnemonicError::InvalidKey => write!(f, "The derivation path gives an invalid key"),
+            MnemonicError::Lock => write!(f, "Could not lock the secrets into memory"),
+        }
+    }
+}
+
+impl std::error::Error for MnemonicError {}
+
+impl Mnemonic {
+    /// The seed of a phrase of English words, with an optional passphrase
+    /// ("" if none). The passphrase must already be NFKD normalized, which
+    /// ASCII always is.
+    pub fn new(phrase: &str, passphrase: &str) -> Result<Self, MnemonicError> {
+        check_phrase(phrase)?;
+        let mut words = Zeroizing::new(String::with_capacity(phrase.len()));
+        for word in phrase.split_whitespace() {
+            if !words.is_empty() {
+                words.push(' ');
+            }
+            words.push_str(word);
+        }
+        let mut salt = Zeroizing::new(String::with_capacity(8 + passphrase.len()));
+        salt.push_str("mnemonic");
+        salt.push_str(passphrase);
+
+        let mut seed = SafeBytes::new(64).map_err(|()| MnemonicError::Lock)?;
+        pbkdf2::pbkdf2_hmac::<Sha512>(words.as_bytes(), salt.as_bytes(), 2048, &mut seed);
+        Ok(Self { seed })
+    }
+
+    /// The key of the Ethereum account at an index, as most wallets derive
+    /// them (m/44'/60'/0'/0/index)
+    pub fn account(&self, index: u32) -> Result<SafeSecretKey, MnemonicError> {
+        self.derive(&format!("{}/{}", ACCOUNT_PATH, index))
+    }
+
+    /// The keys of the first `count` Ethereum accounts
+    pub fn accounts(&self, count: u32) -> Result<Vec<SafeSecretKey>, MnemonicError> {
+        (0..count).map(|index| self.account(index)).collect()
+    }
+
+    /// The key at a BIP-32 path, eg: m/44'/60'/0'/0/0. Hardened indices
+    /// are marked with ' or h.
+    pub fn derive(&self, path: &str) -> Result<SafeSecretKey, MnemonicError> {
+        let indices = parse_path(path)?;
+        let lock = |()| MnemonicError::Lock;
+
+        let mut i = SafeBytes::new(64).map_err(lock)?;
+        hmac_sha512(b"Bitcoin seed", &self.seed, &mut i);
+        if !SafeSecretKey::is_valid(&i[..32]) {
+            return Err(MnemonicError::InvalidKey);
+        }
+        let mut key = SafeSecretKey::from_bytes(&i[..32]).map_err(lock)?;
+        let mut chain_code = SafeBytes::new(32).map_err(lock)?;
+        chain_code.copy_from_slice(&i[32..]);
+
+        let secp = Secp256k1::signing_only();
+        let mut data = SafeBytes::new(37).map_err(lock)?;
+        for index in indices {
+            // Hardened children are derived from the key, and others from
+            // the public key, so that they can be derived without the key
+            if index >= HARDENED {
+                data[0] = 0;
+                data[1..33].copy_from_slice(key.as_bytes());
+            } else {
+                let public = PublicKey::from_secret_key(&secp, &key);
+                data[..33].copy_from_slice(&public.serialize());
+            }
+            data[33..].copy_from_slice(&index.to_be_bytes());
+            hmac_sha512(&chain_code, &data, &mut i);
+            key.add_tweak(&i[..32])
+                .map_err(|()| MnemonicError::InvalidKey)?;
+            chain_code.copy_from_slice(&i[32..]);
+        }
+        Ok(key)
+    }
+}
+
+/// The HMAC is zeroized once it is copied into `out`. The state of the hash
+/// (as with PBKDF2 above) is on the stack, which is neither locked nor
+/// zeroized.
+fn hmac_sha512(key: &[u8], data: &[u8], out: &mut [u8]) {
+    let mut hmac = Hmac::<Sha512>::new_from_slice(key).expect("HMAC takes keys of any length; qed");
+    hmac.update(data);
+    let mut tag = hmac.finalize().into_bytes();
+    out.copy_from_slice(&tag);
+    tag[..].zeroize();
+}
+
+/// Checks that each word is in the English wordlist, and the checksum which
+/// the last word ends with
+fn check_phrase(phrase: &str) -> Result<(), MnemonicError> {
+    let words: Vec<&str> = phrase.split_whitespace().collect();
+    if ![12, 15, 18, 21, 24].contains(&words.len()) {
+        return Err(MnemonicError::InvalidPhrase);
+    }
+    // Each word is 11 bits of the entropy followed by its checksum, which is
+    // 1 bit for each 32 of entropy
+    let mut bits = Zeroizing::new([0u8; 33]);
+    for (i, word) in words.iter().enumerate() {
+        let index = ENGLISH
+            .lines()
+            .position(|w| w == *word)
+            .ok_or(MnemonicError::InvalidPhrase)?;
+        for bit in 0..11 {
+            if index & (1 << (10 - bit)) != 0 {
+                let pos = i * 11 + bit;
+                bits[pos / 8] |= 0x80 >> (pos % 8);
+            }
+        }
+    }
+    let checksum_bits = words.len() / 3;
+    let entropy = &bits[..checksum_bits * 4];
+    let shift = 8 - checksum_bits;
+    if Sha256::digest(entropy)[0] >> shift != bits[entropy.len()] >> shift {
+        return Err(MnemonicError::InvalidPhrase);
+    }
+    Ok(())
+}
+
+fn parse_path(path: &str) -> Result<Vec<u32>, MnemonicError> {
+    let invalid = || MnemonicError::InvalidPath(path.to_owned());
+    let mut parts = path.split('/');
+    if parts.next() != Some("m") {
+        return Err(invalid());
+    }
+    parts
+        .map(|part| {
+            let (index, hardened) = match part.strip_suffix(&['\'', 'h'][..]) {
+                Some(index) => (index, HARDENED),
+                None => (part, 0),
+            };
+            match index.parse::<u32>() {
+                Ok(index) if index < HARDENED => Ok(index | hardened),
+                _ => Err(invalid()),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Signer;
+
+    /// The accounts of the mnemonic which Hardhat and Anvil use
+    #[test]
+    pub fn derives_accounts() {
+        let mnemonic = Mnemonic::new(
+            "test test test test test test test test test test test junk",
+            "",
+        )
+        .unwrap();
+        let accounts = mnemonic.accounts(2).unwrap();
+        assert_eq!(
+            hex::encode(accounts[0].as_bytes()),
+            "ac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80"
+        );
+        assert_eq!(
+            accounts[1].address(),
+            "70997970C51812dc3A010C7d01b50e0d17dc79C8".parse().unwrap()
+        );
+        let derived = mnemonic.derive("m/44h/60h/0h/0/1").unwrap();
+        assert_eq!(derived.as_bytes(), accounts[1].as_bytes());
+    }
+
+    /// A test vector of BIP-39
+    #[test]
+    pub fn seeds_with_passphrase() {
+        let phrase = "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about";
+        let mnemonic = Mnemonic::new(phrase, "TREZOR").unwrap();
+        assert_eq!(
+            hex::encode(&*mnemonic.seed),
+            "c55257c360c07c72029aebc1b53c05ed0362ada38ead3e3e9efa3708e53495531f09a6987599d18264c1e1c92f2cf141630c7a3c4ab7c81b2f001698e7463b04"
+        );
+    }
+
+    #[test]
+    pub fn checks_phrases() {
+        let valid = "legal winner thank year wave sausage worth useful legal winner thank yellow";
+        assert!(Mnemonic::new(valid, "").is_ok());
+        let bad_checksum =
+            "legal winner thank year wave sausage worth useful legal winner thank year";
+        assert!(matches!(
+            Mnemonic::new(bad_checksum, ""),
+            Err(MnemonicError::InvalidPhrase)
+        ));
+        let unknown_word =
+            "legal winner thank year wave sausage worth useful legal winner thank yellows";
+        assert!(matches!(
+            Mnemonic::new(unknown_word, ""),
+            Err(MnemonicError::InvalidPhrase)
+        ));
+    }
+
+    #[test]
+    pub fn parses_paths() {
+        assert_eq!(
+            parse_path("m/44'/60'/0'/0/7").unwrap(),
+            vec![44 | HARDENED, 60 | HARDENED, HARDENED, 0, 7]
+        );
+        assert!(parse_path("m").unwrap().is_empty());
+        for invalid in &["44'/60'", "m/x", "m/2147483648", "m//0"] {
+            assert!(parse_path(invalid).is_err());
+        }
+    }
+}
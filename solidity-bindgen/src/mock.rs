@@ -0,0 +1,357 @@
+use crate::{CallOptions, CallProvider, Context, SendOptions, SendProvider};
+use async_trait::async_trait;
+use ethabi::Token;
+use jsonrpc_core as rpc;
+use std::sync::{Arc, Mutex};
+use web3::contract::tokens::{Detokenize, Tokenize};
+use web3::signing::keccak256;
+use web3::types::{Address, TransactionReceipt, H256, U64};
+
+/// A Context for unit tests of code using generated bindings, which needs no
+/// node. Calls and sends are answered from expectations registered with
+/// `expect`, and every call and send is recorded for later assertions. A call
+/// or send which no expectation matches panics, failing the test.
+///
+/// ```ignore
+/// let mock = MockContext::new();
+/// mock.expect("balanceOf").with_params(owner).returns(U256::from(10));
+/// mock.expect("transfer").with_params((to, U256::from(20))).reverts("Insufficient balance");
+///
+/// let token = Token::new(address, &mock);
+/// assert_eq!(token.balance_of(owner).await?, 10.into());
+/// assert!(token.transfer(to, 20.into()).await.is_err());
+/// assert_eq!(mock.sends()[0].function.as_deref(), Some("transfer"));
+/// ```
+#[derive(Clone, Default)]
+pub struct MockContext(Arc<Mutex<MockState>>);
+
+#[derive(Default)]
+struct MockState {
+    expectations: Vec<Expectation>,
+    calls: Vec<RecordedCall>,
+    sends: Vec<RecordedSend>,
+}
+
+struct Expectation {
+    contract: Option<Address>,
+    function: String,
+    params: Option<Vec<Token>>,
+    response: MockResponse,
+}
+
+enum MockResponse {
+    Return(Vec<Token>),
+    Revert(String),
+}
+
+/// A call made through a MockProvider
+#[derive(Clone, Debug)]
+pub struct RecordedCall {
+    pub contract: Address,
+    pub function: String,
+    pub params: Vec<Token>,
+    pub options: CallOptions,
+}
+
+/// A send made through a MockProvider, including those which reverted
+#[derive(Clone, Debug)]
+pub struct RecordedSend {
+    pub contract: Address,
+    /// The name of the function, or None for calldata sent with `send_data`
+    /// (eg: to the receive or fallback functions)
+    pub function: Option<String>,
+    pub params: Vec<Token>,
+    pub data: Vec<u8>,
+    pub options: Option<SendOptions>,
+    pub confirmations: Option<usize>,
+}
+
+impl MockContext {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers the response for calls or sends of a function. When more
+    /// than one expectation matches, the first registered is used.
+    pub fn expect(&self, function: &str) -> ExpectationBuilder {
+        ExpectationBuilder {
+            context: self.clone(),
+            contract: None,
+            function: function.to_owned(),
+            params: None,
+        }
+    }
+
+    /// All calls made so far, in order
+    pub fn calls(&self) -> Vec<RecordedCall> {
+        self.0.lock().unwrap().calls.clone()
+    }
+
+    /// All sends made so far, in order
+    pub fn sends(&self) -> Vec<RecordedSend> {
+        self.0.lock().unwrap().sends.clone()
+    }
+}
+
+/// Narrows which calls an expectation applies to. Finish with `returns` or
+/// `reverts` to register it.
+pub struct ExpectationBuilder {
+    context: MockContext,
+    contract: Option<Address>,
+    function: String,
+    params: Option<Vec<Token>>,
+}
+
+impl ExpectationBuilder {
+    /// Only match calls to the contract at this address. By default
+    /// contracts at any address match.
+    pub fn on(mut self, contract: Address) -> Self {
+        self.contract = Some(contract);
+        self
+    }
+
+    /// Only match calls with exactly these params. By default any params
+    /// match. Multiple params are given as a tuple.
+    pub fn with_params(mut self, params: impl Tokenize) -> Self {
+        self.params = Some(params.into_tokens());
+        self
+    }
+
+    /// Answer with this output. Multiple outputs are given as a tuple. For
+    /// sends, the output is ignored and the send succeeds.
+    pub fn returns(self, output: impl Tokenize) {
+        self.register(MockResponse::Return(output.into_tokens()))
+    }
+
+    /// Fail with a revert carrying this reason, like `require(false, reason)`
+    pub fn reverts(self, reason: &str) {
+        self.register(MockResponse::Revert(reason.to_owned()))
+    }
+
+    fn register(self, response: MockResponse) {
+        let expectation = Expectation {
+            contract: self.contract,
+            function: self.function,
+            params: self.params,
+            response,
+        };
+        self.context
+            .0
+            .lock()
+            .unwrap()
+            .expectations
+            .push(expectation);
+    }
+}
+
+impl Context for MockContext {
+    type Provider = MockProvider;
+    fn provider(&self, contract: Address, _abi: Arc<ethabi::Contract>) -> Self::Provider {
+        MockProvider {
+            contract,
+            context: self.clone(),
+        }
+    }
+}
+
+/// The provider for a MockContext
+pub struct MockProvider {
+    contract: Address,
+    context: MockContext,
+}
+
+impl MockState {
+    /// Finds the first matching expectation, if any
+    // Option::is_none_or would need Rust 1.82
+    #[allow(clippy::unnecessary_map_or)]
+    fn respond(
+        &self,
+        contract: Address,
+        function: &str,
+        params: &[Token],
+    ) -> Option<Result<Vec<Token>, web3::Error>> {
+        self.expectations
+            .iter()
+            .find(|e| {
+                e.function == function
+                    && e.contract.map_or(true, |c| c == contract)
+                    && e.params.as_ref().map_or(true, |p| p == params)
+            })
+            .map(|e| match &e.response {
+                MockResponse::Return(tokens) => Ok(tokens.clone()),
+                MockResponse::Revert(reason) => Err(revert(reason)),
+            })
+    }
+
+    /// Records a send, with a receipt distinct from those of earlier sends
+    fn send(&mut self, send: RecordedSend) -> TransactionReceipt {
+        let nonce = self.sends.len() as u64;
+        self.sends.push(send);
+        TransactionReceipt {
+            transaction_hash: H256(keccak256(&nonce.to_be_bytes())),
+            status: Some(U64::one()),
+            ..Default::default()
+        }
+    }
+}
+
+/// Builds the error a node returns for `revert(reason)`, with the reason
+/// ABI encoded as `Error(string)` in the data.
+fn revert(reason: &str) -> web3::Error {
+    let mut data = vec![0x08, 0xc3, 0x79, 0xa0];
+    data.extend(ethabi::encode(&[Token::String(reason.to_owned())]));
+    let data: String = data.iter().map(|b| format!("{:02x}", b)).collect();
+    web3::Error::Rpc(rpc::Error {
+        code: rpc::ErrorCode::ServerError(3),
+        message: format!("execution reverted: {}", reason),
+        data: Some(rpc::Value::String(format!("0x{}", data))),
+    })
+}
+
+#[async_trait]
+impl CallProvider for MockProvider {
+    async fn call<Out: Detokenize + Unpin + Send, Params: Tokenize + Send>(
+        &self,
+        name: &'static str,
+        params: Params,
+        options: CallOptions,
+    ) -> Result<Out, web3::Error> {
+        let params = params.into_tokens();
+        let response = {
+            let mut state = self.context.0.lock().unwrap();
+            let response = state.respond(self.contract, name, &params);
+            state.calls.push(RecordedCall {
+                contract: self.contract,
+                function: name.to_owned(),
+                params: params.clone(),
+                options,
+            });
+            response
+        };
+
+        match response {
+            Some(Ok(tokens)) => Ok(Out::from_tokens(tokens).unwrap_or_else(|e| {
+                panic!("Mocked output for {} has the wrong type: {}", name, e)
+            })),
+            Some(Err(e)) => Err(e),
+            None => panic!(
+                "Unexpected call to {} on {:?} with params {:?}",
+                name, self.contract, params
+            ),
+        }
+    }
+}
+
+#[async_trait]
+impl SendProvider for MockProvider {
+    type Out = TransactionReceipt;
+
+    async fn send<Params: Tokenize + Send>(
+        &self,
+        func: &'static str,
+        params: Params,
+        options: Option<SendOptions>,
+        confirmations: Option<usize>,
+    ) -> Result<Self::Out, web3::Error> {
+        let params = params.into_tokens();
+        let (response, receipt) = {
+            let mut state = self.context.0.lock().unwrap();
+            let response = state.respond(self.contract, func, &params);
+            let receipt = state.send(RecordedSend {
+                contract: self.contract,
+                function: Some(func.to_owned()),
+                params: params.clone(),
+                data: Vec::new(),
+                options,
+                confirmations,
+            });
+            (response, receipt)
+        };
+        match response {
+            Some(Ok(_)) => Ok(receipt),
+            Some(Err(e)) => Err(e),
+            None => panic!(
+                "Unexpected send of {} to {:?} with params {:?}",
+                func, self.contract, params
+            ),
+        }
+    }
+
+    /// Calldata isn't matched against expectations, so this always succeeds
+    async fn send_data(
+        &self,
+        data: Vec<u8>,
+        options: Option<SendOptions>,
+        confirmations: Option<usize>,
+    ) -> Result<Self::Out, web3::Error> {
+        let receipt = self.context.0.lock().unwrap().send(RecordedSend {
+            contract: self.contract,
+            function: None,
+            params: Vec::new(),
+            data,
+            options,
+            confirmations,
+        });
+        Ok(receipt)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use web3::types::U256;
+
+    #[test]
+    pub fn answers_and_records() {
+        let mock = MockContext::new();
+        let owner = Address::repeat_byte(1);
+        mock.expect("balanceOf")
+            .with_params(owner)
+            .returns(U256::from(10));
+        mock.expect("balanceOf").returns(U256::zero());
+        mock.expect("transfer").reverts("Insufficient balance");
+        mock.expect("approve").returns(());
+
+        let provider = mock.provider(Address::repeat_byte(2), Default::default());
+        futures::executor::block_on(async {
+            let balance: U256 = provider
+                .call("balanceOf", owner, Default::default())
+                .await
+                .unwrap();
+            assert_eq!(balance, 10.into());
+            let balance: U256 = provider
+                .call("balanceOf", Address::zero(), Default::default())
+                .await
+                .unwrap();
+            assert_eq!(balance, 0.into());
+
+            let err = provider
+                .send("transfer", (owner, U256::one()), None, None)
+                .await
+                .unwrap_err();
+            assert!(err.to_string().contains("Insufficient balance"));
+            provider
+                .send("approve", (owner, U256::one()), None, None)
+                .await
+                .unwrap();
+
+            provider.send_data(vec![1], None, None).await.unwrap();
+        });
+
+        assert_eq!(mock.calls().len(), 2);
+        let sends = mock.sends();
+        assert_eq!(sends.len(), 3);
+        assert_eq!(sends[0].function.as_deref(), Some("transfer"));
+        assert_eq!(sends[1].function.as_deref(), Some("approve"));
+        assert_eq!(sends[2].function, None);
+        assert_eq!(sends[2].data, vec![1]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Unexpected send of transfer")]
+    pub fn panics_on_unexpected_sends() {
+        let mock = MockContext::new();
+        let provider = mock.provider(Address::repeat_byte(2), Default::default());
+        let _ = futures::executor::block_on(provider.send("transfer", (), None, None));
+    }
+}
@@ -0,0 +1,361 @@
+use crate::{CallOptions, CallProvider, Context};
+use ethabi::{Function, Token};
+use std::fmt;
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use web3::contract::tokens::Detokenize;
+use web3::types::{Address, H160};
+
+/// The address Multicall3 is deployed to on most chains.
+/// See https://github.com/mds1/multicall
+pub const MULTICALL3_ADDRESS: Address = H160([
+    0xca, 0x11, 0xbd, 0xe0, 0x59, 0x77, 0xb3, 0x63, 0x11, 0x67, 0x02, 0x88, 0x62, 0xbe, 0x2a, 0x17,
+    0x39, 0x76, 0xca, 0x11,
+]);
+
+const MULTICALL3_ABI: &str = r#"[{
+    "type": "function",
+    "name": "aggregate3",
+    "stateMutability": "payable",
+    "inputs": [{
+        "name": "calls",
+        "type": "tuple[]",
+        "components": [
+            { "name": "target", "type": "address" },
+            { "name": "allowFailure", "type": "bool" },
+            { "name": "callData", "type": "bytes" }
+        ]
+    }],
+    "outputs": [{
+        "name": "returnData",
+        "type": "tuple[]",
+        "components": [
+            { "name": "success", "type": "bool" },
+            { "name": "returnData", "type": "bytes" }
+        ]
+    }]
+}]"#;
+
+/// A call to a contract function which has been encoded but not yet made.
+/// These are created by the `prepare_` methods of generated contracts so that
+/// they can be batched together with a Multicall.
+pub struct PreparedCall<Out> {
+    target: Address,
+    function: Function,
+    params: Vec<Token>,
+    _out: PhantomData<fn() -> Out>,
+}
+
+impl<Out> PreparedCall<Out> {
+    #[doc(hidden)]
+    pub fn new(target: Address, abi: &ethabi::Contract, name: &str, params: Vec<Token>) -> Self {
+        // All of the ABIs are verified at compile time, so we can just unwrap here.
+        let function = abi.function(name).unwrap().clone();
+        Self {
+            target,
+            function,
+            params,
+            _out: PhantomData,
+        }
+    }
+}
+
+/// Identifies the result of a call added to a Multicall
+pub struct CallHandle<Out> {
+    batch: usize,
+    index: usize,
+    _out: PhantomData<fn() -> Out>,
+}
+
+/// Distinguishes the handles of one Multicall from those of another
+static BATCHES: AtomicUsize = AtomicUsize::new(0);
+
+/// A call in a Multicall reverted. Only calls added with `add_allow_failure`
+/// can fail individually, otherwise the whole Multicall fails.
+#[derive(Debug)]
+pub struct Reverted {
+    /// The revert data, which may be empty or encode a reason or custom error.
+    pub data: Vec<u8>,
+}
+
+impl fmt::Display for Reverted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Call reverted")
+    }
+}
+
+impl std::error::Error for Reverted {}
+
+/// Why the result of a call in a Multicall is not available
+#[derive(Debug)]
+pub enum MulticallError {
+    Reverted(Reverted),
+    /// The call returned no data, eg: there is no contract at the target.
+    /// Calls to an address without code succeed, so these are only caught
+    /// when decoding.
+    NoData,
+    /// The handle was returned by a different Multicall
+    UnknownHandle,
+}
+
+impl fmt::Display for MulticallError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MulticallError::Reverted(reverted) => reverted.fmt(f),
+            MulticallError::NoData => write!(f, "Call returned no data"),
+            MulticallError::UnknownHandle => write!(f, "The call is not in this Multicall"),
+        }
+    }
+}
+
+impl std::error::Error for MulticallError {}
+
+/// Batches view calls to any number of contracts into a single eth_call to
+/// Multicall3's `aggregate3`, saving round trips.
+pub struct Multicall<P> {
+    provider: P,
+    batch: usize,
+    options: CallOptions,
+    calls: Vec<Token>,
+    functions: Vec<Function>,
+}
+
+impl<P> Multicall<P>
+where
+    P: CallProvider,
+{
+    /// Uses Multicall3 at its usual address
+    pub fn new<C>(context: &C) -> Self
+    where
+        C: Context<Provider = P>,
+    {
+        Self::at_address(context, MULTICALL3_ADDRESS)
+    }
+
+    /// Uses Multicall3 deployed to a specific address, for chains where the
+    /// usual address is not available.
+    pub fn at_address<C>(context: &C, address: Address) -> Self
+    where
+        C: Context<Provider = P>,
+    {
+        Self {
+            provider: context.provider(
+                address,
+                Arc::new(ethabi::Contract::load(MULTICALL3_ABI.as_bytes()).unwrap()),
+            ),
+            batch: BATCHES.fetch_add(1, Ordering::Relaxed),
+            options: CallOptions::default(),
+            calls: Vec::new(),
+            functions: Vec::new(),
+        }
+    }
+
+    /// Sets the options for the eth_call, eg: the block at which all of the
+    /// calls are made.
+    pub fn with_call_options(mut self, options: CallOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Adds a call to the batch. If this call reverts the whole batch fails.
+    pub fn add<Out>(&mut self, call: PreparedCall<Out>) -> CallHandle<Out> {
+        self.push(call, false)
+    }
+
+    /// Adds a call to the batch which is allowed to revert without failing
+    /// the rest of the batch.
+    pub fn add_allow_failure<Out>(&mut self, call: PreparedCall<Out>) -> CallHandle<Out> {
+        self.push(call, true)
+    }
+
+    fn push<Out>(&mut self, call: PreparedCall<Out>, allow_failure: bool) -> CallHandle<Out> {
+        // The inputs were typed by the code gen, so this cannot fail
+        let data = call.function.encode_input(&call.params).unwrap();
+        self.calls.push(Token::Tuple(vec![
+            Token::Address(call.target),
+            Token::Bool(allow_failure),
+            Token::Bytes(data),
+        ]));
+        self.functions.push(call.function);
+        CallHandle {
+            batch: self.batch,
+            index: self.calls.len() - 1,
+            _out: PhantomData,
+        }
+    }
+
+    /// Makes all of the calls in a single request.
+    pub async fn call(&self) -> Result<MulticallResults, web3::Error> {
+        if self.calls.is_empty() {
+            return Ok(MulticallResults {
+                batch: self.batch,
+                results: Vec::new(),
+                functions: Vec::new(),
+            });
+        }
+
+        let results: Token = self
+            .provider
+            .call(
+                "aggregate3",
+                Token::Array(self.calls.clone()),
+                self.options.clone(),
+            )
+            .await?;
+
+        let results = match results {
+            Token::Array(results) => results,
+            _ => panic!("Unexpected output from aggregate3"),
+        };
+        let results = results
+            .into_iter()
+            .map(|result| match result {
+                Token::Tuple(mut fields) if fields.len() == 2 => {
+                    match (fields.remove(0), fields.remove(0)) {
+                        (Token::Bool(success), Token::Bytes(data)) => (success, data),
+                        _ => panic!("Unexpected output from aggregate3"),
+                    }
+                }
+                _ => panic!("Unexpected output from aggregate3"),
+            })
+            .collect();
+
+        Ok(MulticallResults {
+            batch: self.batch,
+            results,
+            functions: self.functions.clone(),
+        })
+    }
+}
+
+/// The results of a Multicall, in the order the calls were added.
+pub struct MulticallResults {
+    batch: usize,
+    results: Vec<(bool, Vec<u8>)>,
+    functions: Vec<Function>,
+}
+
+impl MulticallResults {
+    /// Decodes the result of one of the calls
+    pub fn get<Out: Detokenize>(&self, handle: &CallHandle<Out>) -> Result<Out, MulticallError> {
+        let (success, data) = match self.results.get(handle.index) {
+            Some(result) if handle.batch == self.batch => result,
+            _ => return Err(MulticallError::UnknownHandle),
+        };
+        if !success {
+            return Err(MulticallError::Reverted(Reverted { data: data.clone() }));
+        }
+        let function = &self.functions[handle.index];
+        if data.is_empty() && !function.outputs.is_empty() {
+            return Err(MulticallError::NoData);
+        }
+        // Like other calls, ABI sorts of errors are considered irrecoverable.
+        // See also the comments in Web3Provider::call
+        let tokens = function.decode_output(data).unwrap_or_else(|e| {
+            panic!(
+                "The ABI is out of date. Name: {}. Inner: {}",
+                function.name, e
+            )
+        });
+        Ok(Out::from_tokens(tokens).unwrap_or_else(|e| {
+            panic!(
+                "The ABI is out of date. Name: {}. Inner: {}",
+                function.name, e
+            )
+        }))
+    }
+
+    pub fn len(&self) -> usize {
+        self.results.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.results.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "evm")]
+    use crate::test_support::secret_key;
+    #[cfg(feature = "evm")]
+    use crate::Evm;
+    #[cfg(feature = "evm")]
+    use web3::types::U256;
+
+    /// The creation code of Multicall3
+    #[cfg(feature = "evm")]
+    const MULTICALL3: &str = include_str!("testdata/multicall3.hex");
+
+    /// Creation code for a contract which returns 42 from every call
+    #[cfg(feature = "evm")]
+    const ANSWER: &str = "600a600c600039600a6000f3602a60005260206000f3";
+
+    /// Creation code for a contract which reverts every call
+    #[cfg(feature = "evm")]
+    const REVERT: &str = "6005600c60003960056000f360006000fd";
+
+    #[cfg(feature = "evm")]
+    const ANSWER_ABI: &str = r#"[{
+        "type": "function",
+        "name": "answer",
+        "stateMutability": "view",
+        "inputs": [],
+        "outputs": [{ "name": "", "type": "uint256" }]
+    }]"#;
+
+    #[test]
+    pub fn aggregate3_abi() {
+        let abi = ethabi::Contract::load(MULTICALL3_ABI.as_bytes()).unwrap();
+        let data = abi
+            .function("aggregate3")
+            .unwrap()
+            .encode_input(&[Token::Array(vec![])])
+            .unwrap();
+        assert_eq!(&data[..4], &[0x82, 0xad, 0x56, 0xcb]);
+    }
+
+    #[cfg(feature = "evm")]
+    #[test]
+    pub fn aggregates_calls() {
+        let evm = Evm::new();
+        let secret_key = secret_key();
+        let context = evm.context(&secret_key);
+        let from = context.from();
+        let multicall3 = evm
+            .deploy(from, hex::decode(MULTICALL3.trim()).unwrap())
+            .unwrap();
+        let answer = evm.deploy(from, hex::decode(ANSWER).unwrap()).unwrap();
+        let revert = evm.deploy(from, hex::decode(REVERT).unwrap()).unwrap();
+
+        let abi = ethabi::Contract::load(ANSWER_ABI.as_bytes()).unwrap();
+        let prepare = |target| PreparedCall::<U256>::new(target, &abi, "answer", Vec::new());
+        let mut multicall = Multicall::at_address(&context, multicall3);
+        let succeeds = multicall.add(prepare(answer));
+        let reverts = multicall.add_allow_failure(prepare(revert));
+        let no_code = multicall.add_allow_failure(prepare(Address::repeat_byte(7)));
+        let results = futures::executor::block_on(multicall.call()).unwrap();
+
+        assert_eq!(results.len(), 3);
+        assert_eq!(results.get(&succeeds).unwrap(), 42.into());
+        assert!(matches!(
+            results.get(&reverts),
+            Err(MulticallError::Reverted(Reverted { data })) if data.is_empty()
+        ));
+        assert!(matches!(results.get(&no_code), Err(MulticallError::NoData)));
+
+        let mut other = Multicall::at_address(&context, multicall3);
+        let foreign = other.add(prepare(answer));
+        assert!(matches!(
+            results.get(&foreign),
+            Err(MulticallError::UnknownHandle)
+        ));
+
+        // Without allowing failure, a revert fails the whole batch
+        let mut multicall = Multicall::at_address(&context, multicall3);
+        multicall.add(prepare(revert));
+        assert!(futures::executor::block_on(multicall.call()).is_err());
+    }
+}
@@ -0,0 +1,146 @@
+use crate::BoxTransport;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Arc, Mutex};
+use web3::api::Eth;
+use web3::types::{Address, BlockNumber, U256};
+
+/// Hands out nonces for the accounts of a context, so that concurrent sends
+/// from one account don't fetch and use the same nonce. The node is only
+/// asked for an account's nonce on its first send and after a resync.
+#[derive(Default)]
+pub(crate) struct NonceManager {
+    /// Each account is locked separately, so that fetching the nonce of one
+    /// doesn't hold up sends from the others
+    accounts: Mutex<HashMap<Address, Arc<tokio::sync::Mutex<Option<Nonces>>>>>,
+}
+
+struct Nonces {
+    next: U256,
+    /// Nonces below `next` which were given out but never broadcast. These
+    /// are gaps which would hold up every later transaction, so are filled
+    /// before any new nonce is given out.
+    gaps: BTreeSet<U256>,
+}
+
+impl NonceManager {
+    pub(crate) async fn next(&self, eth: &Eth<BoxTransport>, from: Address) -> web3::Result<U256> {
+        let account = self.account(from);
+        let mut account = account.lock().await;
+        let nonces = match &mut *account {
+            Some(nonces) => nonces,
+            None => {
+                let next = eth
+                    .transaction_count(from, Some(BlockNumber::Pending))
+                    .await?;
+                account.insert(Nonces {
+                    next,
+                    gaps: BTreeSet::new(),
+                })
+            }
+        };
+        if let Some(gap) = nonces.gaps.iter().next().copied() {
+            nonces.gaps.remove(&gap);
+            return Ok(gap);
+        }
+        let nonce = nonces.next;
+        nonces.next += U256::one();
+        Ok(nonce)
+    }
+
+    /// Returns a nonce which the node rejected the transaction for, so that
+    /// it is used by the next send.
+    pub(crate) async fn release(&self, from: Address, nonce: U256) {
+        if let Some(nonces) = &mut *self.account(from).lock().await {
+            if nonce + U256::one() == nonces.next {
+                nonces.next = nonce;
+            } else if nonce < nonces.next {
+                nonces.gaps.insert(nonce);
+            }
+        }
+    }
+
+    /// Forgets what is known about an account's nonces, so that the next
+    /// send asks the node. This is for when they may be out of sync, eg:
+    /// another process has sent from the account.
+    pub(crate) async fn resync(&self, from: Address) {
+        *self.account(from).lock().await = None;
+    }
+
+    fn account(&self, from: Address) -> Arc<tokio::sync::Mutex<Option<Nonces>>> {
+        let mut accounts = self.accounts.lock().unwrap();
+        accounts.entry(from).or_default().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::Node;
+    use futures::FutureExt as _;
+    use jsonrpc_core as rpc;
+    use serde_json::json;
+    use web3::api::Namespace as _;
+    use web3::{helpers, RequestId, Transport};
+
+    /// A node which never answers
+    #[derive(Clone, Debug)]
+    struct Stalled;
+
+    impl Transport for Stalled {
+        type Out = futures::future::Pending<web3::Result<rpc::Value>>;
+        fn prepare(&self, method: &str, params: Vec<rpc::Value>) -> (RequestId, rpc::Call) {
+            (1, helpers::build_request(1, method, params))
+        }
+        fn send(&self, _id: RequestId, _request: rpc::Call) -> Self::Out {
+            futures::future::pending()
+        }
+    }
+
+    #[test]
+    pub fn sequential_nonces_with_gaps_filled() {
+        // Answers eth_getTransactionCount with 5
+        let node = Node::new(|_, _| json!("0x5"));
+        let eth = Eth::new(BoxTransport::new(node.clone()));
+        let nonces = NonceManager::default();
+        let from = Address::repeat_byte(1);
+
+        futures::executor::block_on(async {
+            let (a, b, c) = futures::join!(
+                nonces.next(&eth, from),
+                nonces.next(&eth, from),
+                nonces.next(&eth, from),
+            );
+            let mut issued = vec![a.unwrap(), b.unwrap(), c.unwrap()];
+            issued.sort();
+            assert_eq!(issued, vec![5.into(), 6.into(), 7.into()]);
+
+            // The last nonce is rolled back, and an earlier one is a gap
+            nonces.release(from, 7.into()).await;
+            nonces.release(from, 5.into()).await;
+            assert_eq!(nonces.next(&eth, from).await.unwrap(), 5.into());
+            assert_eq!(nonces.next(&eth, from).await.unwrap(), 7.into());
+            assert_eq!(node.requests(), 1);
+
+            nonces.resync(from).await;
+            assert_eq!(nonces.next(&eth, from).await.unwrap(), 5.into());
+            assert_eq!(node.requests(), 2);
+        });
+    }
+
+    /// Waiting on the node for one account's nonce doesn't hold up others
+    #[test]
+    pub fn accounts_fetch_independently() {
+        let stalled = Eth::new(BoxTransport::new(Stalled));
+        let eth = Eth::new(BoxTransport::new(Node::new(|_, _| json!("0x5"))));
+        let nonces = NonceManager::default();
+
+        futures::executor::block_on(async {
+            let waiting = nonces.next(&stalled, Address::repeat_byte(1));
+            futures::pin_mut!(waiting);
+            assert!(futures::poll!(&mut waiting).is_pending());
+
+            let other = nonces.next(&eth, Address::repeat_byte(2)).now_or_never();
+            assert_eq!(other.unwrap().unwrap(), 5.into());
+        });
+    }
+}
@@ -0,0 +1,120 @@
+use crate::BlockRef;
+use web3::contract::Options;
+use web3::types::{AccessList, Address, U256};
+
+/// Options which apply to calls (as opposed to sends) of contract functions.
+#[derive(Clone, Debug, Default)]
+pub struct CallOptions {
+    /// The block whose state is read. Defaults to the latest block.
+    pub block: BlockRef,
+    /// The address to call as. Defaults to the address of the context.
+    pub from: Option<Address>,
+    /// Gas limit for the call.
+    pub gas: Option<U256>,
+    /// Gas price for the call.
+    pub gas_price: Option<U256>,
+    /// Value to transfer with the call, for simulating payable functions.
+    pub value: Option<U256>,
+}
+
+impl CallOptions {
+    /// Create new options by modifying the defaults in a closure.
+    pub fn with<F>(func: F) -> CallOptions
+    where
+        F: FnOnce(&mut CallOptions),
+    {
+        let mut options = CallOptions::default();
+        func(&mut options);
+        options
+    }
+}
+
+/// Options which apply to sends of contract functions. Those which are not
+/// given are filled in by the context.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SendOptions {
+    /// The account to send from, which the context must have a signer for.
+    /// Defaults to the primary account of the context.
+    pub from: Option<Address>,
+    /// Gas limit for the transaction.
+    pub gas: Option<U256>,
+    /// Gas price for a legacy transaction. Giving this without either of the
+    /// EIP-1559 fees sends a legacy transaction, whatever the FeeModel of
+    /// the context.
+    pub gas_price: Option<U256>,
+    /// The most to pay per gas, including the priority fee. Giving this or
+    /// `max_priority_fee_per_gas` sends an EIP-1559 transaction, whatever
+    /// the FeeModel of the context. The other is estimated if not given.
+    pub max_fee_per_gas: Option<U256>,
+    /// The most to pay per gas to the block producer, above the base fee.
+    pub max_priority_fee_per_gas: Option<U256>,
+    /// Value to transfer with the transaction.
+    pub value: Option<U256>,
+    /// Nonce for the transaction. By default the context manages nonces.
+    pub nonce: Option<U256>,
+    /// Addresses and storage slots to pay for up front at a discount. See
+    /// EIP-2930. Giving this to a legacy transaction sends it as type 1.
+    pub access_list: Option<AccessList>,
+    /// Asks the node for the access list with eth_createAccessList, and
+    /// attaches it if it reduces the estimated gas. Ignored when
+    /// `access_list` is given.
+    pub create_access_list: bool,
+}
+
+impl SendOptions {
+    /// Create new options by modifying the defaults in a closure.
+    pub fn with<F>(func: F) -> SendOptions
+    where
+        F: FnOnce(&mut SendOptions),
+    {
+        let mut options = SendOptions::default();
+        func(&mut options);
+        options
+    }
+}
+
+/// The access list is carried over, and a `transaction_type` of 1 without
+/// one gives an empty list, so that it is still sent as type 1. Otherwise the
+/// type follows from the fees, as for any SendOptions. `condition`, which
+/// only OpenEthereum supported, is dropped.
+impl From<Options> for SendOptions {
+    fn from(options: Options) -> Self {
+        let access_list = match (options.access_list, options.transaction_type) {
+            (None, Some(kind)) if kind == 1.into() => Some(Vec::new()),
+            (access_list, _) => access_list,
+        };
+        Self {
+            gas: options.gas,
+            gas_price: options.gas_price,
+            value: options.value,
+            nonce: options.nonce,
+            access_list,
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use web3::types::AccessListItem;
+
+    #[test]
+    pub fn converts_web3_options() {
+        let access_list = vec![AccessListItem {
+            address: Address::repeat_byte(1),
+            storage_keys: Vec::new(),
+        }];
+        let options = SendOptions::from(Options::with(|o| {
+            o.gas_price = Some(5.into());
+            o.access_list = Some(access_list.clone());
+        }));
+        assert_eq!(options.gas_price, Some(5.into()));
+        assert_eq!(options.access_list, Some(access_list));
+
+        let options = SendOptions::from(Options::with(|o| o.transaction_type = Some(1.into())));
+        assert_eq!(options.access_list, Some(Vec::new()));
+        let options = SendOptions::from(Options::default());
+        assert_eq!(options.access_list, None);
+    }
+}
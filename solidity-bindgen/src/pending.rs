@@ -0,0 +1,804 @@
+use crate::transaction::{Fees, Transaction};
+use crate::{BlockRef, FeeEstimator, FeeModel, Web3Context};
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::broadcast;
+use web3::api::Namespace as _;
+use web3::helpers;
+use web3::types::{
+    Address, Block, BlockId, BlockNumber, Bytes, TransactionId, TransactionReceipt, H256, U256, U64,
+};
+use web3::Transport as _;
+
+/// How often the node is asked whether a transaction has progressed
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The least that nodes accept fees being raised by to replace a
+/// transaction, in percent. This is geth's default.
+const MIN_BUMP_PERCENT: u64 = 10;
+
+/// A transaction which has been broadcast, as returned by sends through a
+/// Web3Context. Inclusion, confirmations and finality are each awaited
+/// separately, and as often as needed.
+///
+/// Waits follow the transaction through reorgs. A receipt is only returned
+/// once its block is in the canonical chain with enough blocks on top, so
+/// the receipt may be from a different block than was first seen. What
+/// happens along the way can be observed with `events`.
+///
+/// Waits poll the node on a timer, so must be used within a tokio runtime.
+///
+/// ```ignore
+/// let pending = token.transfer(to, 10.into()).await?;
+/// store.save(pending.sent())?;
+/// let receipt = pending.confirmed().await?;
+///
+/// // After a restart
+/// let pending = PendingTransaction::attach(&context, store.load()?);
+/// let receipt = pending.finalized().await?;
+/// ```
+#[derive(Clone)]
+pub struct PendingTransaction {
+    sent: SentTransaction,
+    context: Web3Context,
+    tracking: Arc<Mutex<Tracking>>,
+    events: broadcast::Sender<TransactionEvent>,
+}
+
+/// What is needed to track a transaction after it was sent, which can be
+/// persisted to re-attach with `PendingTransaction::attach`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SentTransaction {
+    pub hash: H256,
+    pub from: Address,
+    pub nonce: U256,
+    /// The number of confirmations awaited by `PendingTransaction::confirmed`
+    pub confirmations: usize,
+    /// Earlier transactions with the same nonce which this one replaced, eg:
+    /// with `PendingTransaction::speed_up`. Any of them may be included
+    /// instead, so all are tracked.
+    #[serde(default)]
+    pub replaces: Vec<H256>,
+    /// What was signed, to sign again with other fees
+    pub(crate) transaction: Transaction,
+}
+
+/// When and by how much `PendingTransaction::escalate` raises fees
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EscalationPolicy {
+    /// How long to wait for inclusion before each bump
+    pub timeout: Duration,
+    /// How much to raise fees by each time, in percent. Nodes reject
+    /// replacements with fees raised by less than 10%.
+    pub bump_percent: u64,
+    /// The most to pay per gas (the gas price or max fee). Fees are raised
+    /// to this once the next bump would pass it, if that is still enough
+    /// to replace the transaction.
+    pub max_fee_per_gas: Option<U256>,
+    pub max_bumps: u32,
+}
+
+impl Default for EscalationPolicy {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(60),
+            bump_percent: 20,
+            max_fee_per_gas: None,
+            max_bumps: 5,
+        }
+    }
+}
+
+/// A change in the state of a transaction, seen while waiting on it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TransactionEvent {
+    /// Included in a block. After a reorg, this may be a different block.
+    Included { block_hash: H256, block_number: U64 },
+    /// The block which included the transaction is no longer canonical. It
+    /// may be included again.
+    Reorged { block_hash: H256, block_number: U64 },
+    /// The node knows nothing of the transaction, eg: it was evicted from
+    /// the mempool. It may still be included if another node has it.
+    Dropped,
+    /// Another transaction with the same nonce was included instead. Waits
+    /// fail after this.
+    Replaced,
+}
+
+/// What has been reported, so that each change is reported once however
+/// many waits are in progress
+#[derive(Default)]
+struct Tracking {
+    included: Option<(H256, U64)>,
+    dropped: bool,
+    replaced: bool,
+}
+
+enum Status {
+    Included(Box<TransactionReceipt>),
+    Pending,
+    Dropped,
+    Replaced,
+}
+
+enum Target {
+    Included,
+    Confirmations(usize),
+    Finalized,
+}
+
+impl PendingTransaction {
+    pub(crate) fn new(context: Web3Context, sent: SentTransaction) -> Self {
+        Self {
+            sent,
+            context,
+            tracking: Default::default(),
+            events: broadcast::channel(16).0,
+        }
+    }
+
+    /// Tracks a transaction sent earlier, eg: by this process before it
+    /// restarted. The context must be connected to the same chain, and for
+    /// replacements must have the key of the sender.
+    pub fn attach(context: &Web3Context, sent: SentTransaction) -> Self {
+        Self::new(context.clone(), sent)
+    }
+
+    pub fn hash(&self) -> H256 {
+        self.sent.hash
+    }
+
+    pub fn sent(&self) -> &SentTransaction {
+        &self.sent
+    }
+
+    /// Receives the events seen by waits on this transaction, or on any of
+    /// its clones, from now on
+    pub fn events(&self) -> broadcast::Receiver<TransactionEvent> {
+        self.events.subscribe()
+    }
+
+    /// Waits for the transaction to be included in a block
+    pub async fn included(&self) -> Result<TransactionReceipt, web3::Error> {
+        self.wait(Target::Included).await
+    }
+
+    /// Waits for the given number of blocks to be mined on top of the block
+    /// which includes the transaction
+    pub async fn confirmations(
+        &self,
+        confirmations: usize,
+    ) -> Result<TransactionReceipt, web3::Error> {
+        self.wait(Target::Confirmations(confirmations)).await
+    }
+
+    /// Waits for the number of confirmations given to the send, or the
+    /// default of the context
+    pub async fn confirmed(&self) -> Result<TransactionReceipt, web3::Error> {
+        self.confirmations(self.sent.confirmations).await
+    }
+
+    /// Waits for the block which includes the transaction to be finalized,
+    /// after which it cannot be reorged. This needs a node which supports
+    /// the `finalized` block tag (post-merge only).
+    pub async fn finalized(&self) -> Result<TransactionReceipt, web3::Error> {
+        self.wait(Target::Finalized).await
+    }
+
+    /// Replaces the transaction with the same one at higher fees, so that
+    /// it is included sooner. Fees are raised by the least that nodes
+    /// accept for a replacement, or to what the market asks now if more.
+    pub async fn speed_up(&self) -> Result<PendingTransaction, web3::Error> {
+        self.speed_up_by(MIN_BUMP_PERCENT).await
+    }
+
+    /// Like `speed_up`, raising fees by at least the given percent
+    pub async fn speed_up_by(&self, percent: u64) -> Result<PendingTransaction, web3::Error> {
+        let fees = self.bumped_fees(percent).await?;
+        self.replace(Transaction {
+            fees,
+            ..self.sent.transaction.clone()
+        })
+        .await
+    }
+
+    /// Replaces the transaction with a transfer of nothing from the sender
+    /// to itself, so that whatever it would have done is not done. Unless
+    /// it is included first, which the returned transaction also tracks.
+    pub async fn cancel(&self) -> Result<PendingTransaction, web3::Error> {
+        let fees = self.bumped_fees(MIN_BUMP_PERCENT).await?;
+        self.replace(Transaction {
+            to: Some(self.sent.from),
+            gas: 21_000.into(),
+            fees,
+            value: U256::zero(),
+            data: Bytes::default(),
+            access_list: None,
+            ..self.sent.transaction.clone()
+        })
+        .await
+    }
+
+    /// Waits for the transaction to be included, speeding it up each time
+    /// the timeout of the policy passes without it. Returns the last
+    /// replacement, which tracks all of the earlier ones. Fails if the
+    /// timeout passes once more after the last bump the policy allows.
+    ///
+    /// This must be used within a tokio runtime, for the timeout.
+    pub async fn escalate(
+        &self,
+        policy: &EscalationPolicy,
+    ) -> Result<PendingTransaction, web3::Error> {
+        let mut current = self.clone();
+        let mut bumps = 0;
+        loop {
+            if let Ok(receipt) = tokio::time::timeout(policy.timeout, current.included()).await {
+                receipt?;
+                return Ok(current);
+            }
+            if bumps == policy.max_bumps {
+                break;
+            }
+            let mut fees = current.bumped_fees(policy.bump_percent).await?;
+            if let Some(max) = policy.max_fee_per_gas {
+                let old = &current.sent.transaction.fees;
+                if fees.max_fee_per_gas() > max {
+                    fees = fees.capped(max);
+                    if !replaces(&fees, old) {
+                        break;
+                    }
+                }
+            }
+            let replacement = Transaction {
+                fees,
+                ..current.sent.transaction.clone()
+            };
+            current = match current.replace(replacement).await {
+                Ok(replacement) => replacement,
+                // Nodes reject replacements once the nonce is used
+                Err(e) => match current.status().await? {
+                    Status::Included(_) => return Ok(current),
+                    _ => return Err(e),
+                },
+            };
+            bumps += 1;
+        }
+        Err(web3::Error::InvalidResponse(format!(
+            "Transaction {:?} was not included after raising its fees {} times",
+            current.sent.hash, bumps
+        )))
+    }
+
+    /// The fees of the transaction raised by a percent, or what the market
+    /// asks now if more
+    async fn bumped_fees(&self, percent: u64) -> Result<Fees, web3::Error> {
+        let bump = |fee| bump(fee, percent);
+        let eth = self.context.eth();
+        Ok(match self.sent.transaction.fees {
+            Fees::Legacy { gas_price } => Fees::Legacy {
+                gas_price: bump(gas_price).max(eth.gas_price().await?),
+            },
+            Fees::Eip1559 {
+                max_fee_per_gas,
+                max_priority_fee_per_gas,
+            } => {
+                let estimator = match self.context.fee_model() {
+                    FeeModel::Eip1559(estimator) => estimator.clone(),
+                    FeeModel::Legacy => FeeEstimator::default(),
+                };
+                let (market_max_fee, market_priority_fee) = estimator.estimate(&eth).await?;
+                let max_priority_fee_per_gas =
+                    bump(max_priority_fee_per_gas).max(market_priority_fee);
+                Fees::Eip1559 {
+                    max_fee_per_gas: bump(max_fee_per_gas)
+                        .max(market_max_fee)
+                        .max(max_priority_fee_per_gas),
+                    max_priority_fee_per_gas,
+                }
+            }
+        })
+    }
+
+    /// Signs and sends a transaction with the same nonce
+    async fn replace(&self, transaction: Transaction) -> Result<PendingTransaction, web3::Error> {
+        let signer = self.context.signer_of(self.sent.from)?;
+        let signed = signer.sign_transaction(&transaction).await?;
+        let hash = self
+            .context
+            .eth()
+            .send_raw_transaction(Bytes(signed.raw))
+            .await?;
+        let mut replaces = self.sent.replaces.clone();
+        replaces.push(self.sent.hash);
+        let sent = SentTransaction {
+            hash,
+            replaces,
+            transaction,
+            ..self.sent.clone()
+        };
+        Ok(Self::new(self.context.clone(), sent))
+    }
+
+    async fn wait(&self, target: Target) -> Result<TransactionReceipt, web3::Error> {
+        loop {
+            let status = self.status().await?;
+            self.observe(&status);
+            match status {
+                Status::Included(receipt) => {
+                    let included = receipt.block_number.unwrap_or_default().as_u64();
+                    let reached = match target {
+                        Target::Included => true,
+                        Target::Confirmations(confirmations) => {
+                            let latest = self.context.eth().block_number().await?;
+                            included + confirmations as u64 <= latest.as_u64()
+                        }
+                        Target::Finalized => self.finalized_block().await? >= included,
+                    };
+                    if reached {
+                        return Ok(*receipt);
+                    }
+                }
+                Status::Replaced => {
+                    return Err(web3::Error::InvalidResponse(format!(
+                        "Transaction {:?} was replaced by another with nonce {}",
+                        self.sent.hash, self.sent.nonce
+                    )))
+                }
+                Status::Pending | Status::Dropped => {}
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    }
+
+    async fn status(&self) -> Result<Status, web3::Error> {
+        if let Some(receipt) = self.receipt().await? {
+            return Ok(Status::Included(Box::new(receipt)));
+        }
+        let eth = self.context.eth();
+        let mined = eth
+            .transaction_count(self.sent.from, Some(BlockNumber::Latest))
+            .await?;
+        if mined > self.sent.nonce {
+            // The transaction may have been included since the receipts were
+            // asked for
+            return Ok(match self.receipt().await? {
+                Some(receipt) => Status::Included(Box::new(receipt)),
+                None => Status::Replaced,
+            });
+        }
+        for hash in self.hashes() {
+            if eth.transaction(TransactionId::Hash(hash)).await?.is_some() {
+                return Ok(Status::Pending);
+            }
+        }
+        Ok(Status::Dropped)
+    }
+
+    /// This transaction and those it replaced, latest first
+    fn hashes(&self) -> impl Iterator<Item = H256> + '_ {
+        std::iter::once(self.sent.hash).chain(self.sent.replaces.iter().rev().copied())
+    }
+
+    /// The receipt, if the transaction (or one it replaced) is in a
+    /// canonical block. Nodes can
+    /// briefly serve receipts from reorged blocks, so the block is checked.
+    async fn receipt(&self) -> Result<Option<TransactionReceipt>, web3::Error> {
+        for hash in self.hashes() {
+            if let Some(receipt) = self.receipt_of(hash).await? {
+                return Ok(Some(receipt));
+            }
+        }
+        Ok(None)
+    }
+
+    async fn receipt_of(&self, hash: H256) -> Result<Option<TransactionReceipt>, web3::Error> {
+        let eth = self.context.eth();
+        let receipt = match eth.transaction_receipt(hash).await? {
+            Some(receipt) => receipt,
+            None => return Ok(None),
+        };
+        let (block_hash, block_number) = match (receipt.block_hash, receipt.block_number) {
+            (Some(hash), Some(number)) => (hash, number),
+            _ => return Ok(None),
+        };
+        let canonical = eth
+            .block(BlockId::Number(BlockNumber::Number(block_number)))
+            .await?
+            .and_then(|block| block.hash);
+        Ok(Some(receipt).filter(|_| canonical == Some(block_hash)))
+    }
+
+    /// Reports the changes since the last status
+    fn observe(&self, status: &Status) {
+        let mut tracking = self.tracking.lock().unwrap();
+        let included = match status {
+            Status::Included(receipt) => receipt.block_hash.zip(receipt.block_number),
+            _ => None,
+        };
+        if tracking.included != included {
+            if let Some((block_hash, block_number)) = tracking.included {
+                self.emit(TransactionEvent::Reorged {
+                    block_hash,
+                    block_number,
+                });
+            }
+            if let Some((block_hash, block_number)) = included {
+                self.emit(TransactionEvent::Included {
+                    block_hash,
+                    block_number,
+                });
+            }
+            tracking.included = included;
+        }
+
+        let dropped = matches!(status, Status::Dropped);
+        if dropped && !tracking.dropped {
+            self.emit(TransactionEvent::Dropped);
+        }
+        tracking.dropped = dropped;
+
+        if matches!(status, Status::Replaced) && !tracking.replaced {
+            self.emit(TransactionEvent::Replaced);
+            tracking.replaced = true;
+        }
+    }
+
+    fn emit(&self, event: TransactionEvent) {
+        // Nobody may be listening
+        let _ = self.events.send(event);
+    }
+
+    /// web3's BlockNumber has no `finalized` tag, so the request is made
+    /// directly
+    async fn finalized_block(&self) -> Result<u64, web3::Error> {
+        let block = self
+            .context
+            .eth()
+            .transport()
+            .execute(
+                "eth_getBlockByNumber",
+                vec![
+                    helpers::serialize(&BlockRef::Finalized),
+                    helpers::serialize(&false),
+                ],
+            )
+            .await?;
+        let block: Option<Block<H256>> =
+            serde_json::from_value(block).map_err(|e| web3::Error::Decoder(format!("{:?}", e)))?;
+        match block.and_then(|block| block.number) {
+            Some(number) => Ok(number.as_u64()),
+            None => Err(web3::Error::InvalidResponse(
+                "The node has no finalized block".to_owned(),
+            )),
+        }
+    }
+}
+
+/// A fee raised by a percent, rounding up
+fn bump(fee: U256, percent: u64) -> U256 {
+    (fee * (100 + percent) + 99) / 100
+}
+
+/// Whether nodes accept a transaction with these fees as a replacement for
+/// one with the old fees
+fn replaces(fees: &Fees, old: &Fees) -> bool {
+    let bumped = |new: U256, old: U256| new >= bump(old, MIN_BUMP_PERCENT);
+    let priority_fee = |fees: &Fees| match *fees {
+        Fees::Legacy { gas_price } => gas_price,
+        Fees::Eip1559 {
+            max_priority_fee_per_gas,
+            ..
+        } => max_priority_fee_per_gas,
+    };
+    bumped(fees.max_fee_per_gas(), old.max_fee_per_gas())
+        && bumped(priority_fee(fees), priority_fee(old))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{secret_key, Node as Stub};
+    use serde_json::Value;
+    use std::collections::HashMap;
+    use web3::signing::keccak256;
+    use web3::types::Transaction as RpcTransaction;
+
+    /// What the node knows, which tests change between polls
+    #[derive(Debug, Default)]
+    struct Chain {
+        latest: u64,
+        canonical: HashMap<u64, H256>,
+        /// The block the node has a receipt from
+        receipt: Option<(H256, u64)>,
+        in_mempool: bool,
+        mined_nonces: u64,
+        gas_price: u64,
+        /// Raw transactions sent
+        sent: Vec<Vec<u8>>,
+        /// Includes the transaction in block 1 once this many are sent
+        include_after: Option<usize>,
+    }
+
+    #[derive(Clone, Debug, Default)]
+    struct Node(Arc<Mutex<Chain>>);
+
+    impl Node {
+        fn set(&self, f: impl FnOnce(&mut Chain)) {
+            f(&mut self.0.lock().unwrap())
+        }
+
+        fn sent(&self) -> Vec<Vec<u8>> {
+            self.0.lock().unwrap().sent.clone()
+        }
+
+        /// A transport to the node
+        fn stub(&self) -> Stub {
+            let node = self.clone();
+            Stub::new(move |method, params| node.answer(method, params))
+        }
+
+        fn answer(&self, method: &str, params: &[Value]) -> Value {
+            let mut chain = self.0.lock().unwrap();
+            match method {
+                "eth_blockNumber" => helpers::serialize(&U64::from(chain.latest)),
+                "eth_getTransactionCount" => helpers::serialize(&U256::from(chain.mined_nonces)),
+                "eth_gasPrice" => helpers::serialize(&U256::from(chain.gas_price)),
+                "eth_getTransactionByHash" if chain.in_mempool => {
+                    helpers::serialize(&RpcTransaction::default())
+                }
+                "eth_sendRawTransaction" => {
+                    let raw: Bytes = serde_json::from_value(params[0].clone()).unwrap();
+                    chain.sent.push(raw.0.clone());
+                    if chain.include_after == Some(chain.sent.len()) {
+                        let block = H256::repeat_byte(0xe);
+                        chain.latest = 1;
+                        chain.canonical.insert(1, block);
+                        chain.receipt = Some((block, 1));
+                    }
+                    helpers::serialize(&H256(keccak256(&raw.0)))
+                }
+                "eth_getTransactionReceipt" => match chain.receipt {
+                    Some((hash, number)) => helpers::serialize(&TransactionReceipt {
+                        transaction_hash: serde_json::from_value(params[0].clone()).unwrap(),
+                        block_hash: Some(hash),
+                        block_number: Some(number.into()),
+                        ..Default::default()
+                    }),
+                    None => Value::Null,
+                },
+                "eth_getBlockByNumber" => {
+                    let number: U64 = serde_json::from_value(params[0].clone()).unwrap();
+                    match chain.canonical.get(&number.as_u64()) {
+                        Some(hash) => helpers::serialize(&Block::<H256> {
+                            hash: Some(*hash),
+                            number: Some(number),
+                            ..Default::default()
+                        }),
+                        None => Value::Null,
+                    }
+                }
+                _ => Value::Null,
+            }
+        }
+    }
+
+    fn attach(node: &Node, nonce: u64) -> PendingTransaction {
+        let context = Web3Context::with_transport(node.stub(), Address::zero(), &secret_key());
+        let sent = SentTransaction {
+            hash: H256::repeat_byte(1),
+            from: Address::zero(),
+            nonce: nonce.into(),
+            confirmations: 1,
+            replaces: Vec::new(),
+            transaction: Transaction {
+                chain_id: 1,
+                nonce: nonce.into(),
+                to: Some(Address::repeat_byte(2)),
+                gas: 50_000.into(),
+                fees: Fees::Legacy {
+                    gas_price: 100.into(),
+                },
+                value: 5.into(),
+                data: Bytes(vec![1, 2, 3]),
+                access_list: None,
+            },
+        };
+        PendingTransaction::attach(&context, sent)
+    }
+
+    type Change = Box<dyn FnOnce(&mut Chain)>;
+
+    /// Makes each change between polls, then returns the events seen
+    async fn drive(
+        node: &Node,
+        mut events: broadcast::Receiver<TransactionEvent>,
+        changes: Vec<Change>,
+    ) -> Vec<TransactionEvent> {
+        tokio::time::sleep(POLL_INTERVAL / 2).await;
+        for change in changes {
+            node.set(change);
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+        let mut seen = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            seen.push(event);
+        }
+        seen
+    }
+
+    #[tokio::test(start_paused = true)]
+    pub async fn follows_reorgs() {
+        let (a, b, c) = (
+            H256::repeat_byte(0xa),
+            H256::repeat_byte(0xb),
+            H256::repeat_byte(0xc),
+        );
+        let node = Node::default();
+        node.set(|chain| {
+            chain.latest = 5;
+            chain.canonical.insert(5, a);
+            chain.receipt = Some((a, 5));
+        });
+        let pending = attach(&node, 0);
+
+        let changes: Vec<Change> = vec![
+            // The node still has the receipt from the reorged block
+            Box::new(move |chain| {
+                chain.canonical.insert(5, c);
+                chain.in_mempool = true;
+            }),
+            Box::new(move |chain| {
+                chain.latest = 6;
+                chain.canonical.insert(6, b);
+                chain.receipt = Some((b, 6));
+            }),
+            Box::new(|chain| chain.latest = 7),
+        ];
+        let (receipt, seen) =
+            futures::join!(pending.confirmed(), drive(&node, pending.events(), changes));
+        assert_eq!(receipt.unwrap().block_hash, Some(b));
+        assert_eq!(
+            seen,
+            vec![
+                TransactionEvent::Included {
+                    block_hash: a,
+                    block_number: 5.into()
+                },
+                TransactionEvent::Reorged {
+                    block_hash: a,
+                    block_number: 5.into()
+                },
+                TransactionEvent::Included {
+                    block_hash: b,
+                    block_number: 6.into()
+                },
+            ]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    pub async fn reports_dropped_and_replaced() {
+        let node = Node::default();
+        node.set(|chain| chain.mined_nonces = 3);
+        let pending = attach(&node, 3);
+
+        let changes: Vec<Change> = vec![
+            Box::new(|chain| chain.in_mempool = true),
+            Box::new(|chain| {
+                chain.in_mempool = false;
+                chain.mined_nonces = 4;
+            }),
+        ];
+        let (receipt, seen) =
+            futures::join!(pending.included(), drive(&node, pending.events(), changes));
+        assert!(receipt.is_err());
+        assert_eq!(
+            seen,
+            vec![TransactionEvent::Dropped, TransactionEvent::Replaced]
+        );
+    }
+
+    #[tokio::test]
+    pub async fn speeds_up_and_cancels() {
+        let secret_key = secret_key();
+        let node = Node::default();
+        node.set(|chain| {
+            chain.gas_price = 50;
+            chain.in_mempool = true;
+        });
+        let pending = attach(&node, 0);
+
+        // The least bump nodes accept, as the market asks for less
+        let faster = pending.speed_up().await.unwrap();
+        let expected = Transaction {
+            fees: Fees::Legacy {
+                gas_price: 110.into(),
+            },
+            ..pending.sent().transaction.clone()
+        };
+        assert_eq!(faster.sent().transaction, expected);
+        assert_eq!(node.sent(), vec![expected.sign(&secret_key).raw]);
+        assert_eq!(faster.sent().replaces, vec![pending.hash()]);
+
+        node.set(|chain| chain.gas_price = 200);
+        let cancelled = faster.cancel().await.unwrap();
+        let expected = Transaction {
+            to: Some(Address::zero()),
+            gas: 21_000.into(),
+            fees: Fees::Legacy {
+                gas_price: 200.into(),
+            },
+            value: U256::zero(),
+            data: Bytes::default(),
+            ..pending.sent().transaction.clone()
+        };
+        assert_eq!(cancelled.sent().transaction, expected);
+        assert_eq!(
+            cancelled.sent().replaces,
+            vec![pending.hash(), faster.hash()]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    pub async fn escalates_until_included() {
+        let node = Node::default();
+        node.set(|chain| {
+            chain.gas_price = 50;
+            chain.in_mempool = true;
+            chain.include_after = Some(2);
+        });
+        let pending = attach(&node, 0);
+        let policy = EscalationPolicy {
+            timeout: Duration::from_secs(10),
+            bump_percent: 20,
+            ..Default::default()
+        };
+
+        let included = pending.escalate(&policy).await.unwrap();
+        assert_eq!(included.sent().replaces.len(), 2);
+        assert_eq!(
+            included.sent().transaction.fees,
+            Fees::Legacy {
+                gas_price: 144.into()
+            }
+        );
+        let receipt = included.included().await.unwrap();
+        assert_eq!(receipt.transaction_hash, included.hash());
+    }
+
+    #[tokio::test(start_paused = true)]
+    pub async fn escalates_to_the_cap() {
+        let node = Node::default();
+        node.set(|chain| {
+            chain.gas_price = 50;
+            chain.in_mempool = true;
+        });
+        let pending = attach(&node, 0);
+        let policy = EscalationPolicy {
+            timeout: Duration::from_secs(10),
+            bump_percent: 20,
+            max_fee_per_gas: Some(135.into()),
+            ..Default::default()
+        };
+
+        // 120, then 144 is capped to 135, which is still a 10% bump. The
+        // next is capped to 135 again, which isn't.
+        let started = tokio::time::Instant::now();
+        assert!(pending.escalate(&policy).await.is_err());
+        assert_eq!(started.elapsed(), Duration::from_secs(30));
+        let secret_key = secret_key();
+        let signed = |gas_price: u64| {
+            Transaction {
+                fees: Fees::Legacy {
+                    gas_price: gas_price.into(),
+                },
+                ..pending.sent().transaction.clone()
+            }
+            .sign(&secret_key)
+            .raw
+        };
+        assert_eq!(node.sent(), vec![signed(120), signed(135)]);
+    }
+}
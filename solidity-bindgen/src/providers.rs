@@ -1,6 +1,6 @@
+use crate::{CallOptions, SendOptions};
 use async_trait::async_trait;
 use web3::contract::tokens::{Detokenize, Tokenize};
-use web3::contract::Options;
 use web3::Error;
 
 #[async_trait]
@@ -9,6 +9,7 @@ pub trait CallProvider {
         &self,
         name: &'static str,
         params: Params,
+        options: CallOptions,
     ) -> Result<Out, Error>;
 }
 
@@ -19,7 +20,17 @@ pub trait SendProvider {
         &self,
         func: &'static str,
         params: Params,
-        options: Option<Options>,
+        options: Option<SendOptions>,
+        confirmations: Option<usize>,
+    ) -> Result<Self::Out, web3::Error>;
+
+    /// Sends arbitrary calldata to the contract rather than calling a
+    /// function from the ABI. This is used for the receive and fallback
+    /// functions, which are how plain ETH transfers reach a contract.
+    async fn send_data(
+        &self,
+        data: Vec<u8>,
+        options: Option<SendOptions>,
         confirmations: Option<usize>,
     ) -> Result<Self::Out, web3::Error>;
 }
@@ -0,0 +1,419 @@
+use crate::signer::{recover, recover_message, signature_from_bytes};
+use crate::transaction::{Fees, SignedTransaction, Transaction};
+use crate::{BoxTransport, Signer, TypedData};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use web3::helpers;
+use web3::signing::Signature;
+use web3::transports::Http;
+use web3::types::{AccessList, Address, Bytes, H256, U256, U64};
+use web3::Transport;
+
+/// Signs with a key held by a separate signing service (eg: Clef or
+/// Web3Signer), using eth_signTransaction and eth_sign. Transactions are
+/// still sent by the context, so the service need not be a node.
+///
+/// What the service signs is checked to be what was asked for, and signed
+/// by the account, so that a misbehaving or misconfigured service can't
+/// have the context send some other transaction.
+///
+/// ```ignore
+/// let signer = RemoteSigner::new("http://localhost:8550", from)?;
+/// let context = Web3Context::with_signer(Http::new(node_url)?, signer);
+/// ```
+#[derive(Clone, Debug)]
+pub struct RemoteSigner {
+    address: Address,
+    transport: BoxTransport,
+}
+
+impl RemoteSigner {
+    pub fn new(url: &str, address: Address) -> Result<Self, web3::Error> {
+        Ok(Self::with_transport(Http::new(url)?, address))
+    }
+
+    /// Reaches the signing service over any web3 transport
+    pub fn with_transport<T>(transport: T, address: Address) -> Self
+    where
+        T: Transport + Send + Sync + 'static,
+        T::Out: Send + 'static,
+    {
+        Self {
+            address,
+            transport: BoxTransport::new(transport),
+        }
+    }
+
+    fn check_signer(&self, method: &str, signer: Address) -> Result<(), web3::Error> {
+        if signer != self.address {
+            return Err(invalid(&format!(
+                "{} signed as {:?} rather than {:?}",
+                method, signer, self.address
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Signer for RemoteSigner {
+    fn address(&self) -> Address {
+        self.address
+    }
+
+    async fn sign_transaction(
+        &self,
+        transaction: &Transaction,
+    ) -> Result<SignedTransaction, web3::Error> {
+        let request = TransactionRequest::new(self.address, transaction);
+        let signed = self
+            .transport
+            .execute("eth_signTransaction", vec![helpers::serialize(&request)])
+            .await?;
+        let raw = match decode(signed)? {
+            Signed::Raw(raw) => raw,
+            Signed::WithTransaction { raw } => raw,
+        };
+        let signature = transaction_signature(transaction, &raw.0)?;
+        // The transaction requested, with that signature, is exactly what
+        // the service signed only if it encodes the same
+        let signed = transaction.with_signature(&signature);
+        if signed.raw != raw.0 {
+            return Err(invalid("eth_signTransaction signed another transaction"));
+        }
+        let signer = match transaction.transaction_type() {
+            Some(_) => recover(&transaction.signing_hash(), &signature)?,
+            None => {
+                let recovery_id = signature.v - (35 + 2 * transaction.chain_id);
+                let signature = Signature {
+                    v: 27 + recovery_id,
+                    ..signature
+                };
+                recover(&transaction.signing_hash(), &signature)?
+            }
+        };
+        self.check_signer("eth_signTransaction", signer)?;
+        Ok(signed)
+    }
+
+    async fn sign_message(&self, message: &[u8]) -> Result<Signature, web3::Error> {
+        let signature = self
+            .transport
+            .execute(
+                "eth_sign",
+                vec![
+                    helpers::serialize(&self.address),
+                    helpers::serialize(&Bytes(message.to_vec())),
+                ],
+            )
+            .await?;
+        let signature = decode_signature(signature)?;
+        self.check_signer("eth_sign", recover_message(message, &signature)?)?;
+        Ok(signature)
+    }
+
+    /// Web3Signer names this eth_signTypedData, as did geth before it was
+    /// versioned (eg: eth_signTypedData_v4 of MetaMask)
+    async fn sign_typed_data(&self, data: &TypedData) -> Result<Signature, web3::Error> {
+        let signature = self
+            .transport
+            .execute(
+                "eth_signTypedData",
+                vec![helpers::serialize(&self.address), helpers::serialize(data)],
+            )
+            .await?;
+        let signature = decode_signature(signature)?;
+        self.check_signer("eth_signTypedData", data.recover(&signature)?)?;
+        Ok(signature)
+    }
+}
+
+fn invalid(reason: &str) -> web3::Error {
+    web3::Error::InvalidResponse(format!("Remote signer: {}", reason))
+}
+
+/// The signature of a raw transaction, with v as `Transaction::sign` has it
+fn transaction_signature(transaction: &Transaction, raw: &[u8]) -> Result<Signature, web3::Error> {
+    let malformed = || invalid("eth_signTransaction returned a malformed transaction");
+    let rlp = match (transaction.transaction_type(), raw.split_first()) {
+        (Some(kind), Some((prefix, rlp))) if kind == *prefix => rlp,
+        (Some(_), _) => return Err(malformed()),
+        (None, _) => raw,
+    };
+    let rlp = rlp::Rlp::new(rlp);
+    let count = rlp.item_count().map_err(|_| malformed())?;
+    let field = |i: usize| -> Result<U256, web3::Error> {
+        let index = count.checked_sub(3 - i).ok_or_else(malformed)?;
+        rlp.val_at(index).map_err(|_| malformed())
+    };
+    let (v, r, s) = (field(0)?, field(1)?, field(2)?);
+    let v = match transaction.transaction_type() {
+        Some(_) if v <= U256::one() => 27 + v.as_u64(),
+        // Per EIP-155, so that it can't be replayed on other chains
+        None if v == (35 + 2 * transaction.chain_id).into()
+            || v == (36 + 2 * transaction.chain_id).into() =>
+        {
+            v.as_u64()
+        }
+        _ => return Err(malformed()),
+    };
+    let (mut r_bytes, mut s_bytes) = (H256::zero(), H256::zero());
+    r.to_big_endian(r_bytes.as_bytes_mut());
+    s.to_big_endian(s_bytes.as_bytes_mut());
+    Ok(Signature {
+        v,
+        r: r_bytes,
+        s: s_bytes,
+    })
+}
+
+fn decode_signature(signature: serde_json::Value) -> Result<Signature, web3::Error> {
+    let signature: Bytes = decode(signature)?;
+    signature_from_bytes(&signature.0)
+}
+
+fn decode<T: serde::de::DeserializeOwned>(value: serde_json::Value) -> Result<T, web3::Error> {
+    serde_json::from_value(value).map_err(|e| web3::Error::Decoder(format!("{:?}", e)))
+}
+
+/// The transaction object of eth_signTransaction
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct TransactionRequest<'a> {
+    from: Address,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    to: Option<Address>,
+    gas: U256,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    gas_price: Option<U256>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_fee_per_gas: Option<U256>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_priority_fee_per_gas: Option<U256>,
+    value: U256,
+    data: &'a Bytes,
+    nonce: U256,
+    chain_id: U64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    access_list: Option<&'a AccessList>,
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    transaction_type: Option<U64>,
+}
+
+impl<'a> TransactionRequest<'a> {
+    fn new(from: Address, transaction: &'a Transaction) -> Self {
+        let (gas_price, max_fee_per_gas, max_priority_fee_per_gas) = match transaction.fees {
+            Fees::Legacy { gas_price } => (Some(gas_price), None, None),
+            Fees::Eip1559 {
+                max_fee_per_gas,
+                max_priority_fee_per_gas,
+            } => (None, Some(max_fee_per_gas), Some(max_priority_fee_per_gas)),
+        };
+        Self {
+            from,
+            to: transaction.to,
+            gas: transaction.gas,
+            gas_price,
+            max_fee_per_gas,
+            max_priority_fee_per_gas,
+            value: transaction.value,
+            data: &transaction.data,
+            nonce: transaction.nonce,
+            chain_id: transaction.chain_id.into(),
+            access_list: transaction.access_list.as_ref(),
+            transaction_type: transaction.transaction_type().map(U64::from),
+        }
+    }
+}
+
+/// Geth and Clef answer eth_signTransaction with the transaction as well,
+/// while others answer with only the raw transaction.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Signed {
+    Raw(Bytes),
+    WithTransaction { raw: Bytes },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::serve_http;
+    use crate::SafeSecretKey;
+    use secp256k1::key::SecretKey;
+    use serde_json::{json, Value};
+    use std::convert::TryFrom as _;
+    use tokio::net::TcpListener;
+    use web3::types::AccessListItem;
+
+    /// Stands in for a signing service, answering each request in turn
+    /// after checking it is the one expected.
+    async fn serve(listener: TcpListener, exchanges: Vec<(&'static str, Value, Value)>) {
+        for (method, params, result) in exchanges {
+            serve_http(&listener, |request| {
+                assert_eq!(request["method"], method);
+                assert_eq!(request["params"], params);
+                json!({"jsonrpc": "2.0", "id": request["id"], "result": result})
+            })
+            .await;
+        }
+    }
+
+    #[tokio::test]
+    pub async fn signs_remotely() {
+        let key = SecretKey::from_slice(&[1; 32]).unwrap();
+        let local = SafeSecretKey::try_from(&key).unwrap();
+        let from = local.address();
+        let tx = Transaction {
+            chain_id: 1,
+            nonce: 3.into(),
+            to: Some(Address::repeat_byte(2)),
+            gas: 50_000.into(),
+            fees: Fees::Eip1559 {
+                max_fee_per_gas: 200.into(),
+                max_priority_fee_per_gas: 2.into(),
+            },
+            value: 5.into(),
+            data: Bytes(vec![1, 2, 3]),
+            access_list: Some(vec![AccessListItem {
+                address: Address::repeat_byte(3),
+                storage_keys: vec![H256::repeat_byte(4)],
+            }]),
+        };
+        let expected = local.sign_transaction(&tx).await.unwrap();
+        let message = local.sign_message(b"hello").await.unwrap();
+        let mut signature = message.r.as_bytes().to_vec();
+        signature.extend_from_slice(message.s.as_bytes());
+        // As a recovery id, which is converted to v
+        signature.push(message.v as u8 - 27);
+        let typed: TypedData = serde_json::from_value(json!({
+            "types": {"Note": [{"name": "text", "type": "string"}]},
+            "primaryType": "Note",
+            "domain": {"name": "Test"},
+            "message": {"text": "hello"}
+        }))
+        .unwrap();
+        let typed_signature = local.sign_typed_data(&typed).await.unwrap();
+        let mut typed_bytes = typed_signature.r.as_bytes().to_vec();
+        typed_bytes.extend_from_slice(typed_signature.s.as_bytes());
+        typed_bytes.push(typed_signature.v as u8);
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let server = tokio::spawn(serve(
+            listener,
+            vec![
+                (
+                    "eth_signTransaction",
+                    json!([{
+                        "from": from,
+                        "to": "0x0202020202020202020202020202020202020202",
+                        "gas": "0xc350",
+                        "maxFeePerGas": "0xc8",
+                        "maxPriorityFeePerGas": "0x2",
+                        "value": "0x5",
+                        "data": "0x010203",
+                        "nonce": "0x3",
+                        "chainId": "0x1",
+                        "accessList": [{
+                            "address": "0x0303030303030303030303030303030303030303",
+                            "storageKeys": [H256::repeat_byte(4)]
+                        }],
+                        "type": "0x2"
+                    }]),
+                    json!({"raw": Bytes(expected.raw.clone()), "tx": {}}),
+                ),
+                (
+                    "eth_sign",
+                    json!([from, "0x68656c6c6f"]),
+                    json!(Bytes(signature)),
+                ),
+                (
+                    "eth_signTypedData",
+                    json!([from, typed]),
+                    json!(Bytes(typed_bytes)),
+                ),
+            ],
+        ));
+
+        let remote = RemoteSigner::new(&url, from).unwrap();
+        assert_eq!(remote.sign_transaction(&tx).await.unwrap(), expected);
+        let signed = remote.sign_message(b"hello").await.unwrap();
+        assert_eq!(
+            (signed.v, signed.r, signed.s),
+            (message.v, message.r, message.s)
+        );
+        let signed = remote.sign_typed_data(&typed).await.unwrap();
+        assert_eq!(typed.recover(&signed).unwrap(), from);
+        server.await.unwrap();
+    }
+
+    /// What is signed by another account, or is not what was asked for,
+    /// is refused
+    #[tokio::test]
+    pub async fn refuses_other_signatures() {
+        let local = SafeSecretKey::try_from(&SecretKey::from_slice(&[1; 32]).unwrap()).unwrap();
+        let other = SafeSecretKey::try_from(&SecretKey::from_slice(&[2; 32]).unwrap()).unwrap();
+        let from = local.address();
+        let tx = Transaction {
+            chain_id: 1,
+            nonce: 3.into(),
+            to: Some(Address::repeat_byte(2)),
+            gas: 50_000.into(),
+            fees: Fees::Legacy {
+                gas_price: 200.into(),
+            },
+            value: 5.into(),
+            data: Bytes(vec![1, 2, 3]),
+            access_list: None,
+        };
+        let redirected = Transaction {
+            to: Some(Address::repeat_byte(9)),
+            ..tx.clone()
+        };
+        let by_other = other.sign_transaction(&tx).await.unwrap().raw;
+        let not_requested = local.sign_transaction(&redirected).await.unwrap().raw;
+        let message = other.sign_message(b"hello").await.unwrap();
+        let mut signature = message.r.as_bytes().to_vec();
+        signature.extend_from_slice(message.s.as_bytes());
+        signature.push(message.v as u8);
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let request = json!([TransactionRequest::new(from, &tx)]);
+        let server = tokio::spawn(serve(
+            listener,
+            vec![
+                (
+                    "eth_signTransaction",
+                    request.clone(),
+                    json!(Bytes(by_other)),
+                ),
+                (
+                    "eth_signTransaction",
+                    request.clone(),
+                    json!(Bytes(not_requested)),
+                ),
+                ("eth_signTransaction", request, json!("0x0102")),
+                (
+                    "eth_sign",
+                    json!([from, "0x68656c6c6f"]),
+                    json!(Bytes(signature)),
+                ),
+            ],
+        ));
+
+        let remote = RemoteSigner::new(&url, from).unwrap();
+        for _ in 0..3 {
+            assert!(matches!(
+                remote.sign_transaction(&tx).await,
+                Err(web3::Error::InvalidResponse(_))
+            ));
+        }
+        assert!(matches!(
+            remote.sign_message(b"hello").await,
+            Err(web3::Error::InvalidResponse(_))
+        ));
+        server.await.unwrap();
+    }
+}
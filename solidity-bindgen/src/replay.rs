@@ -0,0 +1,264 @@
+use futures::future::BoxFuture;
+use jsonrpc_core as rpc;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead as _, BufReader, Write as _};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use web3::error::Error;
+use web3::{helpers, RequestId, Transport};
+
+/// One request and its response, as stored in a fixture file
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+struct Exchange {
+    method: String,
+    params: rpc::Value,
+    #[serde(flatten)]
+    response: Response,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum Response {
+    Result(rpc::Value),
+    Error(rpc::Error),
+}
+
+fn method_and_params(request: &rpc::Call) -> (String, rpc::Value) {
+    match request {
+        rpc::Call::MethodCall(call) => (call.method.clone(), helpers::serialize(&call.params)),
+        rpc::Call::Notification(call) => (call.method.clone(), helpers::serialize(&call.params)),
+        rpc::Call::Invalid { .. } => (String::new(), rpc::Value::Null),
+    }
+}
+
+/// Wraps a transport, writing every request and response to a fixture file
+/// which can be served back by `Replay`. Use with `Web3Context::with_transport`
+/// to capture a session against a dev node.
+///
+/// Each response is appended to the file as a line of JSON, so it is complete
+/// even if the process exits abruptly. Only successes and JSON-RPC errors are
+/// recorded. Other errors (eg: the node being unreachable) are passed through.
+/// Failing to write the file doesn't fail the request, as the node has
+/// already acted on it, so it is only logged.
+#[derive(Clone)]
+pub struct Recording<T> {
+    inner: T,
+    path: Arc<PathBuf>,
+    /// Created on the first response
+    file: Arc<Mutex<Option<File>>>,
+}
+
+impl<T> Recording<T> {
+    /// Starts a new recording, replacing any fixture already at `path`
+    pub fn new(inner: T, path: impl AsRef<Path>) -> Self {
+        Self {
+            inner,
+            path: Arc::new(path.as_ref().to_owned()),
+            file: Default::default(),
+        }
+    }
+}
+
+fn append(path: &Path, file: &Mutex<Option<File>>, exchange: &Exchange) -> std::io::Result<()> {
+    let mut line = serde_json::to_vec(exchange)?;
+    line.push(b'\n');
+    let mut file = file.lock().unwrap();
+    let file = match &mut *file {
+        Some(file) => file,
+        None => file.insert(File::create(path)?),
+    };
+    file.write_all(&line)
+}
+
+impl<T: fmt::Debug> fmt::Debug for Recording<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Recording")
+            .field("inner", &self.inner)
+            .field("path", &self.path)
+            .finish()
+    }
+}
+
+impl<T> Transport for Recording<T>
+where
+    T: Transport,
+    T::Out: Send + 'static,
+{
+    type Out = BoxFuture<'static, web3::Result<rpc::Value>>;
+
+    fn prepare(&self, method: &str, params: Vec<rpc::Value>) -> (RequestId, rpc::Call) {
+        self.inner.prepare(method, params)
+    }
+
+    fn send(&self, id: RequestId, request: rpc::Call) -> Self::Out {
+        let (method, params) = method_and_params(&request);
+        let response = self.inner.send(id, request);
+        let path = self.path.clone();
+        let file = self.file.clone();
+        Box::pin(async move {
+            let result = response.await;
+            let response = match &result {
+                Ok(value) => Response::Result(value.clone()),
+                Err(Error::Rpc(e)) => Response::Error(e.clone()),
+                Err(_) => return result,
+            };
+
+            let exchange = Exchange {
+                method,
+                params,
+                response,
+            };
+            if let Err(e) = append(&path, &file, &exchange) {
+                log::warn!("Could not record {} to {:?}: {}", exchange.method, path, e);
+            }
+            result
+        })
+    }
+}
+
+/// Serves responses from a fixture file written by `Recording`, with no
+/// network. Each request must exactly match the method and params of a
+/// recorded request which has not yet been served. Recorded requests are
+/// served in order, so repeated identical requests (eg: polling for a
+/// receipt) get the responses that they got when recorded.
+#[derive(Clone)]
+pub struct Replay {
+    id: Arc<AtomicUsize>,
+    exchanges: Arc<Mutex<Vec<Option<Exchange>>>>,
+}
+
+impl Replay {
+    pub fn from_file(path: impl AsRef<Path>) -> web3::Result<Self> {
+        let file = File::open(path)?;
+        let mut exchanges = Vec::new();
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            if !line.trim().is_empty() {
+                exchanges.push(serde_json::from_str::<Exchange>(&line)?);
+            }
+        }
+        Ok(Self {
+            id: Arc::new(AtomicUsize::new(1)),
+            exchanges: Arc::new(Mutex::new(exchanges.into_iter().map(Some).collect())),
+        })
+    }
+
+    /// The number of recorded requests which have not been made. A test may
+    /// assert this is 0 to check that the whole session was replayed.
+    pub fn remaining(&self) -> usize {
+        let exchanges = self.exchanges.lock().unwrap();
+        exchanges.iter().filter(|e| e.is_some()).count()
+    }
+}
+
+impl fmt::Debug for Replay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Replay")
+            .field("remaining", &self.remaining())
+            .finish()
+    }
+}
+
+impl Transport for Replay {
+    type Out = futures::future::Ready<web3::Result<rpc::Value>>;
+
+    fn prepare(&self, method: &str, params: Vec<rpc::Value>) -> (RequestId, rpc::Call) {
+        let id = self.id.fetch_add(1, Ordering::AcqRel);
+        (id, helpers::build_request(id, method, params))
+    }
+
+    fn send(&self, _id: RequestId, request: rpc::Call) -> Self::Out {
+        let (method, params) = method_and_params(&request);
+        let mut exchanges = self.exchanges.lock().unwrap();
+        let exchange = exchanges.iter_mut().find(|e| match e {
+            Some(e) => e.method == method && e.params == params,
+            None => false,
+        });
+        let result = match exchange.and_then(Option::take) {
+            Some(Exchange {
+                response: Response::Result(value),
+                ..
+            }) => Ok(value),
+            Some(Exchange {
+                response: Response::Error(e),
+                ..
+            }) => Err(Error::Rpc(e)),
+            None => Err(Error::Transport(format!(
+                "No recorded response for {} with params {}",
+                method, params
+            ))),
+        };
+        futures::future::ready(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// Stands in for a node, answering every request with its params
+    #[derive(Clone, Debug)]
+    struct Echo;
+
+    impl Transport for Echo {
+        type Out = futures::future::Ready<web3::Result<rpc::Value>>;
+        fn prepare(&self, method: &str, params: Vec<rpc::Value>) -> (RequestId, rpc::Call) {
+            (1, helpers::build_request(1, method, params))
+        }
+        fn send(&self, _id: RequestId, request: rpc::Call) -> Self::Out {
+            let (_, params) = method_and_params(&request);
+            futures::future::ready(Ok(params))
+        }
+    }
+
+    /// A path which no other test, including in other processes, writes to
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "solidity-bindgen-{}-{}.jsonl",
+            name,
+            std::process::id()
+        ))
+    }
+
+    #[test]
+    pub fn records_and_replays() {
+        let path = temp_path("records-and-replays");
+
+        let recording = Recording::new(Echo, &path);
+        futures::executor::block_on(async {
+            recording.execute("a", vec![json!(1)]).await.unwrap();
+            recording.execute("b", vec![json!(2)]).await.unwrap();
+            recording.execute("a", vec![json!(3)]).await.unwrap();
+        });
+
+        let replay = Replay::from_file(&path).unwrap();
+        futures::executor::block_on(async {
+            assert_eq!(
+                replay.execute("b", vec![json!(2)]).await.unwrap(),
+                json!([2])
+            );
+            assert!(replay.execute("b", vec![json!(2)]).await.is_err());
+            assert!(replay.execute("a", vec![json!(2)]).await.is_err());
+            assert_eq!(
+                replay.execute("a", vec![json!(3)]).await.unwrap(),
+                json!([3])
+            );
+        });
+        assert_eq!(replay.remaining(), 1);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    pub fn responds_when_recording_fails() {
+        let path = temp_path("missing").join("fixture.jsonl");
+        let recording = Recording::new(Echo, &path);
+        let result = futures::executor::block_on(recording.execute("a", vec![json!(1)]));
+        assert_eq!(result.unwrap(), json!([1]));
+        assert!(!path.exists());
+    }
+}
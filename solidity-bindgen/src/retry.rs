@@ -0,0 +1,273 @@
+use crate::transports::{method, sends_transaction};
+use futures::future::BoxFuture;
+use jsonrpc_core as rpc;
+use rand::Rng as _;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::ErrorKind;
+use std::sync::Arc;
+use std::time::Duration;
+use web3::error::Error;
+use web3::{RequestId, Transport};
+
+/// How many times, and how quickly, to retry requests for a method
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    /// The delay before the first retry, which doubles for each retry after
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl RetryPolicy {
+    pub fn never() -> Self {
+        Self {
+            max_retries: 0,
+            ..Default::default()
+        }
+    }
+
+    /// The delay before a retry, with up to half of it randomly taken off so
+    /// that many clients failing at once don't all retry at once.
+    fn backoff(&self, retry: u32) -> Duration {
+        let backoff = self
+            .initial_backoff
+            .checked_mul(1 << retry.min(31))
+            .unwrap_or(self.max_backoff)
+            .min(self.max_backoff);
+        backoff.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 5,
+            initial_backoff: Duration::from_millis(250),
+            max_backoff: Duration::from_secs(10),
+        }
+    }
+}
+
+/// Wraps a transport, retrying requests which fail for reasons that are
+/// likely to pass, eg: rate limiting, 5xx responses and dropped connections.
+/// Use with `Web3Context::with_transport`.
+///
+/// Requests which send a transaction are only retried when the node cannot
+/// have seen them, eg: it refused the connection or rate limited the
+/// request. Otherwise the transaction may have been broadcast already.
+///
+/// This must be used within a tokio runtime.
+#[derive(Clone)]
+pub struct Retry<T> {
+    inner: T,
+    default: RetryPolicy,
+    policies: Arc<HashMap<String, RetryPolicy>>,
+}
+
+impl<T> Retry<T> {
+    /// Retries all methods with the default RetryPolicy
+    pub fn new(inner: T) -> Self {
+        Self {
+            inner,
+            default: RetryPolicy::default(),
+            policies: Default::default(),
+        }
+    }
+
+    /// The policy for methods without their own policy
+    pub fn with_default_policy(mut self, policy: RetryPolicy) -> Self {
+        self.default = policy;
+        self
+    }
+
+    /// The policy for one method, eg: "eth_call"
+    pub fn with_policy(mut self, method: &str, policy: RetryPolicy) -> Self {
+        Arc::make_mut(&mut self.policies).insert(method.to_owned(), policy);
+        self
+    }
+
+    fn policy(&self, method: &str) -> &RetryPolicy {
+        self.policies.get(method).unwrap_or(&self.default)
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Retry<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Retry")
+            .field("inner", &self.inner)
+            .field("default", &self.default)
+            .field("policies", &self.policies)
+            .finish()
+    }
+}
+
+/// What a failure says about whether the request may succeed if retried
+#[derive(Debug, PartialEq, Eq)]
+enum Failure {
+    /// The request was not processed, eg: the connection was refused
+    Rejected,
+    /// The request may have been processed, eg: the connection was reset
+    Transient,
+    /// Retrying will fail the same way, eg: the call reverted
+    Permanent,
+}
+
+fn classify(error: &Error) -> Failure {
+    match error {
+        Error::Unreachable => Failure::Rejected,
+        Error::Transport(message) => match status_code(message) {
+            Some(429) => Failure::Rejected,
+            Some(500..=599) => Failure::Transient,
+            Some(_) => Failure::Permanent,
+            None if message.contains("ConnectError") || message.contains("ConnectionRefused") => {
+                Failure::Rejected
+            }
+            None => Failure::Transient,
+        },
+        Error::Io(e) if e.kind() == ErrorKind::ConnectionRefused => Failure::Rejected,
+        Error::Io(_) => Failure::Transient,
+        Error::Rpc(e) => {
+            let message = e.message.to_lowercase();
+            match e.code {
+                // Rate limiting, as returned by Infura, Alchemy and others
+                rpc::ErrorCode::ServerError(-32005) | rpc::ErrorCode::ServerError(429) => {
+                    Failure::Rejected
+                }
+                _ if message.contains("rate limit") || message.contains("too many requests") => {
+                    Failure::Rejected
+                }
+                // A load balanced node which is behind the others
+                _ if message.contains("header not found") => Failure::Transient,
+                _ => Failure::Permanent,
+            }
+        }
+        _ => Failure::Permanent,
+    }
+}
+
+/// Finds the HTTP status in errors from the Http and BatchingHttp
+/// transports, eg: "Unexpected response status code: 503 Service Unavailable"
+fn status_code(message: &str) -> Option<u16> {
+    let start = message.find("status code")? + "status code".len();
+    let code = message[start..]
+        .trim_start_matches(|c: char| c == ':' || c.is_whitespace())
+        .get(..3)?;
+    code.parse().ok()
+}
+
+impl<T> Transport for Retry<T>
+where
+    T: Transport + Send + Sync + 'static,
+    T::Out: Send + 'static,
+{
+    type Out = BoxFuture<'static, web3::Result<rpc::Value>>;
+
+    fn prepare(&self, method: &str, params: Vec<rpc::Value>) -> (RequestId, rpc::Call) {
+        self.inner.prepare(method, params)
+    }
+
+    fn send(&self, id: RequestId, request: rpc::Call) -> Self::Out {
+        let method = method(&request).to_owned();
+        let policy = self.policy(&method).clone();
+        let this = self.clone();
+        Box::pin(async move {
+            let mut retry = 0;
+            loop {
+                let result = this.inner.send(id, request.clone()).await;
+                let error = match &result {
+                    Err(e) if retry < policy.max_retries => e,
+                    _ => return result,
+                };
+                let retryable = match classify(error) {
+                    Failure::Rejected => true,
+                    Failure::Transient => !sends_transaction(&method),
+                    Failure::Permanent => false,
+                };
+                if !retryable {
+                    return result;
+                }
+                tokio::time::sleep(policy.backoff(retry)).await;
+                retry += 1;
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use web3::helpers;
+
+    /// Fails with each of the errors in turn, then succeeds
+    #[derive(Clone, Debug)]
+    struct Flaky(Arc<Mutex<Vec<Error>>>);
+
+    impl Flaky {
+        fn new(errors: Vec<Error>) -> Self {
+            Self(Arc::new(Mutex::new(errors)))
+        }
+    }
+
+    impl Transport for Flaky {
+        type Out = futures::future::Ready<web3::Result<rpc::Value>>;
+        fn prepare(&self, method: &str, params: Vec<rpc::Value>) -> (RequestId, rpc::Call) {
+            (1, helpers::build_request(1, method, params))
+        }
+        fn send(&self, _id: RequestId, _request: rpc::Call) -> Self::Out {
+            let mut errors = self.0.lock().unwrap();
+            if errors.is_empty() {
+                futures::future::ready(Ok(rpc::Value::Bool(true)))
+            } else {
+                futures::future::ready(Err(errors.remove(0)))
+            }
+        }
+    }
+
+    fn status(code: &str) -> Error {
+        Error::Transport(format!("Unexpected response status code: {}", code))
+    }
+
+    #[tokio::test]
+    pub async fn retries_by_method_and_error() {
+        let policy = RetryPolicy {
+            max_retries: 2,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(1),
+        };
+
+        let retry = Retry::new(Flaky::new(vec![
+            status("503 Service Unavailable"),
+            status("429 Too Many Requests"),
+        ]))
+        .with_default_policy(policy.clone());
+        assert!(retry.execute("eth_call", vec![]).await.is_ok());
+
+        // The transaction may have been broadcast before the 503
+        let retry = Retry::new(Flaky::new(vec![status("503 Service Unavailable")]))
+            .with_default_policy(policy.clone());
+        assert!(retry
+            .execute("eth_sendRawTransaction", vec![])
+            .await
+            .is_err());
+
+        let retry = Retry::new(Flaky::new(vec![status("429 Too Many Requests")]))
+            .with_default_policy(policy.clone());
+        assert!(retry
+            .execute("eth_sendRawTransaction", vec![])
+            .await
+            .is_ok());
+
+        let retry = Retry::new(Flaky::new(vec![Error::Unreachable]))
+            .with_default_policy(policy)
+            .with_policy("eth_call", RetryPolicy::never());
+        assert!(retry.execute("eth_call", vec![]).await.is_err());
+
+        assert_eq!(
+            status_code("Unexpected status code 502 Bad Gateway: "),
+            Some(502)
+        );
+        assert_eq!(classify(&status("401 Unauthorized")), Failure::Permanent);
+    }
+}
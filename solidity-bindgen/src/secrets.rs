@@ -1,9 +1,10 @@
+use secp256k1::constants::CURVE_ORDER;
 use secp256k1::key::{SecretKey, ONE_KEY};
 
 use sodiumoxide::utils::{mlock, munlock};
 use std::convert::TryFrom;
 use std::fmt;
-use std::ops::Deref;
+use std::ops::{Deref, DerefMut};
 use std::pin::Pin;
 use std::slice;
 use zeroize::{DefaultIsZeroes, Zeroize};
@@ -68,6 +69,36 @@ impl SafeSecretKey {
         // not the underlying allocation.
         Ok(Self { safe })
     }
+
+    /// Copies a key from bytes straight into locked memory, so that no
+    /// other SecretKey is made along the way. Fails if the bytes are not a
+    /// valid key or the memory can't be locked.
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Result<Self, ()> {
+        if !Self::is_valid(bytes) {
+            return Err(());
+        }
+        let mut safe = Pin::new(Box::<ZeroizedSecretKey>::default());
+        let mem = safe.as_mut_bytes();
+        mlock(mem)?;
+        mem.copy_from_slice(bytes);
+        Ok(Self { safe })
+    }
+
+    /// Whether bytes are a valid key, as secp256k1 checks
+    pub(crate) fn is_valid(bytes: &[u8]) -> bool {
+        bytes.len() == 32 && bytes.iter().any(|b| *b != 0) && bytes < &CURVE_ORDER[..]
+    }
+
+    /// The key as bytes, which must not be copied
+    pub(crate) fn as_bytes(&self) -> &[u8] {
+        &self.safe.0[..]
+    }
+
+    /// Adds a tweak to the key in place, modulo the curve order. Fails if
+    /// the tweak or resulting key is not a valid key.
+    pub(crate) fn add_tweak(&mut self, tweak: &[u8]) -> Result<(), ()> {
+        self.safe.0.add_assign(tweak).map_err(|_| ())
+    }
 }
 
 impl<'a> TryFrom<&'a SecretKey> for SafeSecretKey {
@@ -94,6 +125,39 @@ impl Deref for SafeSecretKey {
     }
 }
 
+/// Secret bytes other than keys (eg: seeds), with the same care as
+/// SafeSecretKey. The bytes are locked into memory and zeroized on drop.
+pub(crate) struct SafeBytes(Box<[u8]>);
+
+impl SafeBytes {
+    /// Zeroed bytes, to be written in place
+    pub(crate) fn new(len: usize) -> Result<Self, ()> {
+        let mut bytes = vec![0; len].into_boxed_slice();
+        mlock(&mut bytes)?;
+        Ok(Self(bytes))
+    }
+}
+
+impl Drop for SafeBytes {
+    fn drop(&mut self) {
+        self.0.zeroize();
+        let _ignore = munlock(&mut self.0);
+    }
+}
+
+impl Deref for SafeBytes {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl DerefMut for SafeBytes {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.0
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -107,4 +171,13 @@ mod tests {
         let safe = SafeSecretKey::new(&key).unwrap();
         drop(safe);
     }
+
+    #[test]
+    pub fn from_bytes_checks_range() {
+        let key = SafeSecretKey::from_bytes(&[1; 32]).unwrap();
+        assert_eq!(*key, SecretKey::from_slice(&[1; 32]).unwrap());
+        assert!(SafeSecretKey::from_bytes(&[0; 32]).is_err());
+        assert!(SafeSecretKey::from_bytes(&CURVE_ORDER).is_err());
+        assert!(SafeSecretKey::from_bytes(&[1; 31]).is_err());
+    }
 }
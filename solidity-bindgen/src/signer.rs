@@ -0,0 +1,160 @@
+use crate::transaction::{SignedTransaction, Transaction};
+use crate::{SafeSecretKey, TypedData};
+use async_trait::async_trait;
+use web3::signing::{keccak256, Key as _, RecoveryError, SecretKeyRef, Signature};
+use web3::types::{Address, H256};
+
+/// Signs on behalf of an account. A context sends with a signer, which may
+/// hold the key itself (as SafeSecretKey does) or ask something else to
+/// sign, eg: a signing service or a hardware module.
+#[async_trait]
+pub trait Signer: Send + Sync {
+    /// The account which this signs for
+    fn address(&self) -> Address;
+
+    async fn sign_transaction(
+        &self,
+        transaction: &Transaction,
+    ) -> Result<SignedTransaction, web3::Error>;
+
+    /// Signs a message as eth_sign does, which prefixes it per EIP-191 so
+    /// that it can't be a transaction.
+    async fn sign_message(&self, message: &[u8]) -> Result<Signature, web3::Error>;
+
+    /// Signs structured data per EIP-712, as eth_signTypedData does. Not
+    /// all signers can, so by default this fails.
+    async fn sign_typed_data(&self, _data: &TypedData) -> Result<Signature, web3::Error> {
+        Err(web3::Error::Decoder(
+            "typed data signing not supported".to_owned(),
+        ))
+    }
+
+    /// Whether this account signed a message, as `sign_message` does
+    fn verify_message(&self, message: &[u8], signature: &Signature) -> bool {
+        recover_message(message, signature).is_ok_and(|signer| signer == self.address())
+    }
+}
+
+#[async_trait]
+impl Signer for SafeSecretKey {
+    fn address(&self) -> Address {
+        SecretKeyRef::new(self).address()
+    }
+
+    async fn sign_transaction(
+        &self,
+        transaction: &Transaction,
+    ) -> Result<SignedTransaction, web3::Error> {
+        Ok(transaction.sign(&**self))
+    }
+
+    async fn sign_message(&self, message: &[u8]) -> Result<Signature, web3::Error> {
+        Ok(self.sign_hash(&hash_message(message)))
+    }
+
+    async fn sign_typed_data(&self, data: &TypedData) -> Result<Signature, web3::Error> {
+        Ok(self.sign_hash(&data.hash()?.0))
+    }
+}
+
+impl SafeSecretKey {
+    fn sign_hash(&self, hash: &[u8; 32]) -> Signature {
+        SecretKeyRef::new(self)
+            .sign(hash, None)
+            .expect("hash is non-zero 32-bytes; qed")
+    }
+}
+
+/// The account which signed a message per EIP-191, as eth_sign and
+/// personal_sign do
+pub fn recover_message(message: &[u8], signature: &Signature) -> Result<Address, web3::Error> {
+    recover(&hash_message(message), signature)
+}
+
+/// A signature as the 65 bytes of r, s and v, as off-chain services
+/// usually take them
+pub fn signature_to_bytes(signature: &Signature) -> [u8; 65] {
+    let mut bytes = [0; 65];
+    bytes[..32].copy_from_slice(signature.r.as_bytes());
+    bytes[32..64].copy_from_slice(signature.s.as_bytes());
+    bytes[64] = signature.v as u8;
+    bytes
+}
+
+/// A signature from the 65 bytes of r, s and v. Some signers give the
+/// recovery id (0 or 1) rather than v, which is converted.
+pub fn signature_from_bytes(bytes: &[u8]) -> Result<Signature, web3::Error> {
+    match bytes {
+        [rs @ .., v] if rs.len() == 64 => Ok(Signature {
+            r: H256::from_slice(&rs[..32]),
+            s: H256::from_slice(&rs[32..]),
+            v: if *v < 27 { *v as u64 + 27 } else { *v as u64 },
+        }),
+        _ => Err(web3::Error::Decoder(format!(
+            "Expected a 65 byte signature, got {} bytes",
+            bytes.len()
+        ))),
+    }
+}
+
+/// The account which signed a hash
+pub(crate) fn recover(hash: &[u8; 32], signature: &Signature) -> Result<Address, web3::Error> {
+    let recovery_id = match signature.v {
+        0 | 1 => signature.v,
+        27 | 28 => signature.v - 27,
+        _ => return Err(RecoveryError::InvalidSignature.into()),
+    };
+    let mut rs = [0; 64];
+    rs[..32].copy_from_slice(signature.r.as_bytes());
+    rs[32..].copy_from_slice(signature.s.as_bytes());
+    Ok(web3::signing::recover(hash, &rs, recovery_id as i32)?)
+}
+
+/// The EIP-191 hash of a message, as signed by eth_sign
+pub(crate) fn hash_message(message: &[u8]) -> [u8; 32] {
+    let mut prefixed = format!("\x19Ethereum Signed Message:\n{}", message.len()).into_bytes();
+    prefixed.extend_from_slice(message);
+    keccak256(&prefixed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use secp256k1::key::SecretKey;
+    use std::convert::TryFrom as _;
+    use web3::api::Namespace as _;
+
+    /// Messages are signed the same as by web3
+    #[test]
+    pub fn signs_messages_like_web3() {
+        let key = SecretKey::from_slice(&[1; 32]).unwrap();
+        let safe = SafeSecretKey::try_from(&key).unwrap();
+        let signature = futures::executor::block_on(safe.sign_message(b"hello")).unwrap();
+
+        let transport = web3::transports::Http::new("http://localhost").unwrap();
+        let accounts = web3::api::Accounts::new(transport);
+        let expected = accounts.sign("hello", &key);
+        assert_eq!(signature.r, expected.r);
+        assert_eq!(signature.s, expected.s);
+        assert_eq!(signature.v, expected.v as u64);
+        assert_eq!(Signer::address(&safe), SecretKeyRef::new(&key).address());
+    }
+
+    #[test]
+    pub fn recovers_messages() {
+        let key = SecretKey::from_slice(&[1; 32]).unwrap();
+        let transport = web3::transports::Http::new("http://localhost").unwrap();
+        let context = crate::Web3Context::with_transport(transport, Address::zero(), &key);
+        let address = SecretKeyRef::new(&key).address();
+
+        let signature = futures::executor::block_on(context.sign_message(b"log in")).unwrap();
+        assert_eq!(recover_message(b"log in", &signature).unwrap(), address);
+        assert!(context.signer().verify_message(b"log in", &signature));
+        assert!(!context.signer().verify_message(b"log out", &signature));
+
+        let bytes = signature_to_bytes(&signature);
+        let decoded = signature_from_bytes(&bytes).unwrap();
+        assert_eq!(recover_message(b"log in", &decoded).unwrap(), address);
+        assert!(signature_from_bytes(&bytes[1..]).is_err());
+    }
+}
@@ -0,0 +1,100 @@
+//! Helpers shared by the tests of several modules
+
+use jsonrpc_core as rpc;
+use secp256k1::key::SecretKey;
+use serde_json::Value;
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+use tokio::net::TcpListener;
+use web3::{helpers, RequestId, Transport};
+
+/// The key which tests sign with
+pub(crate) fn secret_key() -> SecretKey {
+    SecretKey::from_slice(&[1; 32]).unwrap()
+}
+
+/// What a Node answers a request with, given its method and params
+type Answer = dyn Fn(&str, &[Value]) -> Value + Send + Sync;
+
+/// Stands in for a node, answering each request at once with what `answer`
+/// makes of its method and params. The requests are counted.
+#[derive(Clone)]
+pub(crate) struct Node {
+    answer: Arc<Answer>,
+    requests: Arc<AtomicUsize>,
+}
+
+impl Node {
+    pub(crate) fn new(answer: impl Fn(&str, &[Value]) -> Value + Send + Sync + 'static) -> Self {
+        Self {
+            answer: Arc::new(answer),
+            requests: Arc::default(),
+        }
+    }
+
+    /// The number of requests answered so far
+    pub(crate) fn requests(&self) -> usize {
+        self.requests.load(Ordering::SeqCst)
+    }
+}
+
+impl fmt::Debug for Node {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Node")
+            .field("requests", &self.requests())
+            .finish()
+    }
+}
+
+impl Transport for Node {
+    type Out = futures::future::Ready<web3::Result<rpc::Value>>;
+
+    fn prepare(&self, method: &str, params: Vec<rpc::Value>) -> (RequestId, rpc::Call) {
+        (1, helpers::build_request(1, method, params))
+    }
+
+    fn send(&self, _id: RequestId, request: rpc::Call) -> Self::Out {
+        let call = match request {
+            rpc::Call::MethodCall(call) => call,
+            _ => unreachable!(),
+        };
+        let params: Vec<Value> = call.params.parse().unwrap();
+        self.requests.fetch_add(1, Ordering::SeqCst);
+        futures::future::ready(Ok((self.answer)(&call.method, &params)))
+    }
+}
+
+/// Serves a single HTTP request, answering its JSON body with the JSON
+/// which `respond` makes of it.
+pub(crate) async fn serve_http(listener: &TcpListener, respond: impl FnOnce(Value) -> Value) {
+    let (mut socket, _) = listener.accept().await.unwrap();
+    let mut buf = Vec::new();
+    let body = loop {
+        let mut chunk = [0u8; 4096];
+        let n = socket.read(&mut chunk).await.unwrap();
+        buf.extend_from_slice(&chunk[..n]);
+        let text = String::from_utf8_lossy(&buf).to_string();
+        if let Some(split) = text.find("\r\n\r\n") {
+            let len: usize = text
+                .lines()
+                .find_map(|l| {
+                    l.to_lowercase()
+                        .strip_prefix("content-length: ")
+                        .map(|v| v.trim().parse().unwrap())
+                })
+                .unwrap();
+            if buf.len() >= split + 4 + len {
+                break buf[split + 4..split + 4 + len].to_vec();
+            }
+        }
+    };
+    let body = respond(serde_json::from_slice(&body).unwrap()).to_string();
+    let response = format!(
+        "HTTP/1.1 200 OK\r\nconnection: close\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\r\n{}",
+        body.len(),
+        body
+    );
+    socket.write_all(response.as_bytes()).await.unwrap();
+}
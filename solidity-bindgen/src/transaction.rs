@@ -0,0 +1,327 @@
+use rlp::RlpStream;
+use serde::{Deserialize, Serialize};
+use web3::signing::{keccak256, Key, Signature};
+use web3::types::{AccessList, Address, Bytes, H256, U256};
+
+/// How a transaction pays for gas, which also decides its type
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Fees {
+    /// A legacy (type 0) transaction
+    #[serde(rename_all = "camelCase")]
+    Legacy { gas_price: U256 },
+    /// An EIP-1559 (type 2) transaction
+    #[serde(rename_all = "camelCase")]
+    Eip1559 {
+        max_fee_per_gas: U256,
+        max_priority_fee_per_gas: U256,
+    },
+}
+
+impl Fees {
+    /// The most the transaction can pay per gas
+    pub fn max_fee_per_gas(&self) -> U256 {
+        match self {
+            Fees::Legacy { gas_price } => *gas_price,
+            Fees::Eip1559 {
+                max_fee_per_gas, ..
+            } => *max_fee_per_gas,
+        }
+    }
+
+    /// The fees with the most paid per gas lowered to `max` if above it
+    pub(crate) fn capped(&self, max: U256) -> Fees {
+        match *self {
+            Fees::Legacy { gas_price } => Fees::Legacy {
+                gas_price: gas_price.min(max),
+            },
+            Fees::Eip1559 {
+                max_fee_per_gas,
+                max_priority_fee_per_gas,
+            } => Fees::Eip1559 {
+                max_fee_per_gas: max_fee_per_gas.min(max),
+                max_priority_fee_per_gas: max_priority_fee_per_gas.min(max),
+            },
+        }
+    }
+}
+
+/// A transaction to be signed. web3 can only sign legacy transactions, so
+/// the encoding is done here. Signers which hold a key can use `sign`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Transaction {
+    pub chain_id: u64,
+    pub nonce: U256,
+    pub to: Option<Address>,
+    pub gas: U256,
+    pub fees: Fees,
+    pub value: U256,
+    pub data: Bytes,
+    /// Legacy transactions with an access list are sent as type 1
+    pub access_list: Option<AccessList>,
+}
+
+/// A transaction encoded for eth_sendRawTransaction
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SignedTransaction {
+    pub hash: H256,
+    pub raw: Vec<u8>,
+}
+
+impl Transaction {
+    /// The EIP-2718 type, which is None for legacy transactions
+    pub fn transaction_type(&self) -> Option<u8> {
+        match (&self.fees, &self.access_list) {
+            (Fees::Legacy { .. }, None) => None,
+            // See EIP-2930
+            (Fees::Legacy { .. }, Some(_)) => Some(1),
+            // See EIP-1559
+            (Fees::Eip1559 { .. }, _) => Some(2),
+        }
+    }
+
+    pub fn sign(&self, key: impl Key) -> SignedTransaction {
+        let hash = self.signing_hash();
+        let signature = match self.transaction_type() {
+            Some(_) => key.sign(&hash, None),
+            None => key.sign(&hash, Some(self.chain_id)),
+        }
+        .expect("hash is non-zero 32-bytes; qed");
+        self.with_signature(&signature)
+    }
+
+    /// The number of fields which are signed
+    fn fields(&self) -> usize {
+        match self.transaction_type() {
+            None => 6,
+            Some(1) => 8,
+            _ => 9,
+        }
+    }
+
+    /// The hash which is signed
+    pub(crate) fn signing_hash(&self) -> [u8; 32] {
+        let prefix = self.transaction_type();
+        let mut rlp = RlpStream::new();
+        match prefix {
+            Some(_) => rlp.begin_list(self.fields()),
+            // See EIP-155
+            None => rlp.begin_list(self.fields() + 3),
+        };
+        self.append_fields(&mut rlp);
+        if prefix.is_none() {
+            rlp.append(&self.chain_id);
+            rlp.append(&0u8);
+            rlp.append(&0u8);
+        }
+        keccak256(&with_prefix(prefix, rlp.as_raw()))
+    }
+
+    /// Encodes the transaction with a signature of its signing hash, whose v
+    /// is as `Key::sign` gives it: with the chain id for legacy
+    /// transactions, and 27 or 28 for typed ones.
+    pub(crate) fn with_signature(&self, signature: &Signature) -> SignedTransaction {
+        let prefix = self.transaction_type();
+        let v = match prefix {
+            // Typed transactions have the y parity rather than v
+            Some(_) => signature.v - 27,
+            None => signature.v,
+        };
+
+        let mut rlp = RlpStream::new();
+        rlp.begin_list(self.fields() + 3);
+        self.append_fields(&mut rlp);
+        rlp.append(&v);
+        rlp.append(&U256::from_big_endian(signature.r.as_bytes()));
+        rlp.append(&U256::from_big_endian(signature.s.as_bytes()));
+        let raw = with_prefix(prefix, rlp.as_raw());
+
+        SignedTransaction {
+            hash: H256(keccak256(&raw)),
+            raw,
+        }
+    }
+
+    /// Appends the fields which are signed, in the order for the type
+    fn append_fields(&self, rlp: &mut RlpStream) {
+        let typed = self.transaction_type().is_some();
+        if typed {
+            rlp.append(&self.chain_id);
+        }
+        rlp.append(&self.nonce);
+        match &self.fees {
+            Fees::Legacy { gas_price } => {
+                rlp.append(gas_price);
+            }
+            Fees::Eip1559 {
+                max_fee_per_gas,
+                max_priority_fee_per_gas,
+            } => {
+                rlp.append(max_priority_fee_per_gas);
+                rlp.append(max_fee_per_gas);
+            }
+        }
+        rlp.append(&self.gas);
+        match &self.to {
+            Some(to) => rlp.append(to),
+            None => rlp.append(&""),
+        };
+        rlp.append(&self.value);
+        rlp.append(&self.data.0);
+        if typed {
+            let access_list = self.access_list.as_deref().unwrap_or_default();
+            rlp.begin_list(access_list.len());
+            for item in access_list {
+                rlp.begin_list(2);
+                rlp.append(&item.address);
+                rlp.append_list(&item.storage_keys);
+            }
+        }
+    }
+}
+
+fn with_prefix(prefix: Option<u8>, rlp: &[u8]) -> Vec<u8> {
+    prefix.into_iter().chain(rlp.iter().copied()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use secp256k1::key::SecretKey;
+    use web3::api::Namespace as _;
+    use web3::signing::SecretKeyRef;
+    use web3::types::AccessListItem;
+
+    /// Legacy transactions are signed the same as by web3
+    #[test]
+    pub fn legacy_matches_web3() {
+        let key = SecretKey::from_slice(&[1; 32]).unwrap();
+        let tx = Transaction {
+            chain_id: 1,
+            nonce: 7.into(),
+            to: Some(Address::repeat_byte(2)),
+            gas: 21000.into(),
+            fees: Fees::Legacy {
+                gas_price: 1_000_000_000.into(),
+            },
+            value: 5.into(),
+            data: Bytes(vec![1, 2, 3]),
+            access_list: None,
+        };
+        let signed = tx.sign(SecretKeyRef::new(&key));
+
+        // With every field given, web3 makes no requests
+        let transport = web3::transports::Http::new("http://localhost").unwrap();
+        let accounts = web3::api::Accounts::new(transport);
+        let params = web3::types::TransactionParameters {
+            nonce: Some(tx.nonce),
+            to: tx.to,
+            gas: tx.gas,
+            gas_price: Some(1_000_000_000.into()),
+            value: tx.value,
+            data: tx.data.clone(),
+            chain_id: Some(1),
+            ..Default::default()
+        };
+        let expected =
+            futures::executor::block_on(accounts.sign_transaction(params, &key)).unwrap();
+        assert_eq!(signed.raw, expected.raw_transaction.0);
+        assert_eq!(signed.hash, expected.transaction_hash);
+    }
+
+    fn signature(r: &str, s: &str, y_parity: u64) -> Signature {
+        Signature {
+            r: r.parse().unwrap(),
+            s: s.parse().unwrap(),
+            v: 27 + y_parity,
+        }
+    }
+
+    fn sender(tx: &Transaction, signature: &Signature) -> Address {
+        let mut rs = [0; 64];
+        rs[..32].copy_from_slice(signature.r.as_bytes());
+        rs[32..].copy_from_slice(signature.s.as_bytes());
+        let recovery_id = (signature.v - 27) as i32;
+        web3::signing::recover(&tx.signing_hash(), &rs, recovery_id).unwrap()
+    }
+
+    /// An EIP-2930 transaction on mainnet, as ethers-core decodes it
+    #[test]
+    pub fn encodes_type_1() {
+        let raw = "01f90126018223ff850a02ffee00830f4240940000000000a8fb09af944ab3baf7a9b3e1ab29d880b876200200001525000000000b69ffb300000000557b933a7c2c45672b610f8954a3deb39a51a8cae53ec727dbdeb9e2d5456c3be40cff031ab40a55724d5c9c618a2152e99a45649a3b8cf198321f46720b722f4ec38f99ba3bb1303258d2e816e6a95b25647e01bd0967c1b9599fa3521939871d1d0888f845d694724d5c9c618a2152e99a45649a3b8cf198321f46c0d694720b722f4ec38f99ba3bb1303258d2e816e6a95bc0d69425647e01bd0967c1b9599fa3521939871d1d0888c001a08323efae7b9993bd31a58da7924359d24b5504aa2b33194fcc5ae206e65d2e62a054ce201e3b4b5cd38eb17c56ee2f9111b2e164efcd57b3e70fa308a0a51f7014";
+        let access_list = [
+            "724d5c9c618a2152e99a45649a3b8cf198321f46",
+            "720b722f4ec38f99ba3bb1303258d2e816e6a95b",
+            "25647e01bd0967c1b9599fa3521939871d1d0888",
+        ];
+        let tx = Transaction {
+            chain_id: 1,
+            nonce: 9215.into(),
+            to: Some("0000000000a8fb09af944ab3baf7a9b3e1ab29d8".parse().unwrap()),
+            gas: 1_000_000.into(),
+            fees: Fees::Legacy {
+                gas_price: 43_000_000_000u64.into(),
+            },
+            value: 0.into(),
+            data: Bytes(hex::decode("200200001525000000000b69ffb300000000557b933a7c2c45672b610f8954a3deb39a51a8cae53ec727dbdeb9e2d5456c3be40cff031ab40a55724d5c9c618a2152e99a45649a3b8cf198321f46720b722f4ec38f99ba3bb1303258d2e816e6a95b25647e01bd0967c1b9599fa3521939871d1d0888").unwrap()),
+            access_list: Some(
+                access_list
+                    .iter()
+                    .map(|address| AccessListItem {
+                        address: address.parse().unwrap(),
+                        storage_keys: Vec::new(),
+                    })
+                    .collect(),
+            ),
+        };
+        let signature = signature(
+            "8323efae7b9993bd31a58da7924359d24b5504aa2b33194fcc5ae206e65d2e62",
+            "54ce201e3b4b5cd38eb17c56ee2f9111b2e164efcd57b3e70fa308a0a51f7014",
+            1,
+        );
+        assert_eq!(tx.transaction_type(), Some(1));
+        assert_eq!(hex::encode(tx.with_signature(&signature).raw), raw);
+        assert_eq!(
+            sender(&tx, &signature),
+            "e9c790e8fde820ded558a4771b72eec916c04763".parse().unwrap()
+        );
+    }
+
+    /// An EIP-1559 transaction on mainnet, as alloy-consensus has it
+    #[test]
+    pub fn encodes_type_2() {
+        let tx = Transaction {
+            chain_id: 1,
+            nonce: 0x42.into(),
+            to: Some("6069a6c32cf691f5982febae4faf8a6f3ab2f0f6".parse().unwrap()),
+            gas: 44386.into(),
+            fees: Fees::Eip1559 {
+                max_fee_per_gas: 0x4a817c800u64.into(),
+                max_priority_fee_per_gas: 0x3b9aca00.into(),
+            },
+            value: 0.into(),
+            data: Bytes(hex::decode("a22cb4650000000000000000000000005eee75727d804a2b13038928d36f8b188945a57a0000000000000000000000000000000000000000000000000000000000000000").unwrap()),
+            access_list: None,
+        };
+        let signature = signature(
+            "840cfc572845f5786e702984c2a582528cad4b49b2a10b9db1be7fca90058565",
+            "25e7109ceb98168d95b09b18bbf6b685130e0562f233877d492b94eee0c5b6d1",
+            0,
+        );
+        assert_eq!(
+            hex::encode(tx.signing_hash()),
+            "0d5688ac3897124635b6cf1bc0e29d6dfebceebdc10a54d74f2ef8b56535b682"
+        );
+        assert_eq!(
+            tx.with_signature(&signature).hash,
+            "0ec0b6a2df4d87424e5f6ad2a654e27aaeb7dac20ae9e8385cc09087ad532ee0"
+                .parse()
+                .unwrap()
+        );
+        assert_eq!(
+            sender(&tx, &signature),
+            "dd6b8b3dc6b7ad97db52f08a275ff4483e024cea".parse().unwrap()
+        );
+    }
+}
@@ -0,0 +1,557 @@
+use futures::channel::oneshot;
+use futures::future::BoxFuture;
+use jsonrpc_core as rpc;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use web3::api::SubscriptionId;
+use web3::error::Error;
+use web3::transports::WebSocket;
+use web3::{helpers, DuplexTransport, RequestId, Transport};
+
+/// The method of a request, or "" if the request is invalid
+pub(crate) fn method(request: &rpc::Call) -> &str {
+    match request {
+        rpc::Call::MethodCall(call) => &call.method,
+        rpc::Call::Notification(call) => &call.method,
+        rpc::Call::Invalid { .. } => "",
+    }
+}
+
+/// Whether repeating a request could send a transaction twice. Transports
+/// which repeat requests (eg: Retry and ReconnectingWebSocket) only repeat
+/// these when the node cannot have seen them.
+pub(crate) fn sends_transaction(method: &str) -> bool {
+    matches!(method, "eth_sendRawTransaction" | "eth_sendTransaction")
+}
+
+/// A web3 Transport with its type erased, so that contexts and providers are
+/// not generic over the transport.
+#[derive(Clone)]
+pub struct BoxTransport(Arc<dyn DynTransport>);
+
+trait DynTransport: Send + Sync {
+    fn prepare(&self, method: &str, params: Vec<rpc::Value>) -> (RequestId, rpc::Call);
+    fn send(
+        &self,
+        id: RequestId,
+        request: rpc::Call,
+    ) -> BoxFuture<'static, web3::Result<rpc::Value>>;
+}
+
+impl<T> DynTransport for T
+where
+    T: Transport + Send + Sync + 'static,
+    T::Out: Send + 'static,
+{
+    fn prepare(&self, method: &str, params: Vec<rpc::Value>) -> (RequestId, rpc::Call) {
+        Transport::prepare(self, method, params)
+    }
+    fn send(
+        &self,
+        id: RequestId,
+        request: rpc::Call,
+    ) -> BoxFuture<'static, web3::Result<rpc::Value>> {
+        Box::pin(Transport::send(self, id, request))
+    }
+}
+
+impl BoxTransport {
+    pub fn new<T>(transport: T) -> Self
+    where
+        T: Transport + Send + Sync + 'static,
+        T::Out: Send + 'static,
+    {
+        Self(Arc::new(transport))
+    }
+}
+
+impl fmt::Debug for BoxTransport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BoxTransport").finish()
+    }
+}
+
+impl Transport for BoxTransport {
+    type Out = BoxFuture<'static, web3::Result<rpc::Value>>;
+    fn prepare(&self, method: &str, params: Vec<rpc::Value>) -> (RequestId, rpc::Call) {
+        self.0.prepare(method, params)
+    }
+    fn send(&self, id: RequestId, request: rpc::Call) -> Self::Out {
+        self.0.send(id, request)
+    }
+}
+
+/// Many providers cap the size of batches, commonly at 100 requests.
+const MAX_BATCH_SIZE: usize = 100;
+
+/// An HTTP transport which, rather than sending each request as it is made,
+/// waits a short window for other requests and sends them all as a single
+/// JSON-RPC batch. This is an alternative to Multicall for nodes which don't
+/// have it deployed, and also works for requests other than eth_call.
+///
+/// This must be used within a tokio runtime.
+#[derive(Clone)]
+pub struct BatchingHttp(Arc<BatchingHttpInner>);
+
+struct BatchingHttpInner {
+    client: reqwest::Client,
+    url: reqwest::Url,
+    window: Duration,
+    id: AtomicUsize,
+    queue: Mutex<Queue>,
+}
+
+#[derive(Default)]
+struct Queue {
+    pending: Vec<Pending>,
+    /// Counts the batches taken, so that the timer of a window only flushes
+    /// the batch which started it, and not a later one
+    batch: u64,
+}
+
+impl Queue {
+    fn take(&mut self) -> Vec<Pending> {
+        self.batch += 1;
+        std::mem::take(&mut self.pending)
+    }
+}
+
+struct Pending {
+    id: RequestId,
+    request: rpc::Call,
+    reply: oneshot::Sender<web3::Result<rpc::Value>>,
+}
+
+impl BatchingHttp {
+    /// Requests are sent at most `window` after they are made, or sooner if
+    /// the batch fills up or `flush` is called.
+    pub fn new(url: &str, window: Duration) -> web3::Result<Self> {
+        let url = url
+            .parse()
+            .map_err(|e| Error::Transport(format!("{:?}", e)))?;
+        let client = reqwest::Client::builder()
+            .build()
+            .map_err(|e| Error::Transport(format!("{:?}", e)))?;
+        let inner = BatchingHttpInner {
+            client,
+            url,
+            window,
+            id: AtomicUsize::new(1),
+            queue: Mutex::default(),
+        };
+        Ok(Self(Arc::new(inner)))
+    }
+
+    /// Sends all requests made so far without waiting for the window to
+    /// elapse. This can be used to explicitly group requests by making them
+    /// concurrently and then flushing.
+    pub fn flush(&self) {
+        let batch = self.0.queue.lock().unwrap().take();
+        self.spawn_batch(batch);
+    }
+
+    /// Sends the batch started by a window, unless it was already sent
+    fn flush_window(&self, window: u64) {
+        let batch = {
+            let mut queue = self.0.queue.lock().unwrap();
+            if queue.batch != window {
+                return;
+            }
+            queue.take()
+        };
+        self.spawn_batch(batch);
+    }
+
+    fn spawn_batch(&self, batch: Vec<Pending>) {
+        if batch.is_empty() {
+            return;
+        }
+        let this = self.clone();
+        tokio::spawn(async move { this.send_batch(batch).await });
+    }
+
+    async fn send_batch(&self, batch: Vec<Pending>) {
+        let mut replies = HashMap::with_capacity(batch.len());
+        let mut requests = Vec::with_capacity(batch.len());
+        for pending in batch {
+            replies.insert(pending.id, pending.reply);
+            requests.push(pending.request);
+        }
+
+        match self.post(rpc::Request::Batch(requests)).await {
+            // Responses may come back in any order, so are matched up by id
+            Ok(outputs) => {
+                for output in outputs {
+                    let reply = match output.id() {
+                        rpc::Id::Num(id) => replies.remove(&(*id as RequestId)),
+                        _ => None,
+                    };
+                    if let Some(reply) = reply {
+                        let _ignore = reply.send(helpers::to_result_from_output(output));
+                    }
+                }
+                for (_, reply) in replies {
+                    let _ignore = reply.send(Err(Error::InvalidResponse(
+                        "Batch response is missing a request".to_owned(),
+                    )));
+                }
+            }
+            Err(e) => {
+                for (_, reply) in replies {
+                    let _ignore = reply.send(Err(e.clone()));
+                }
+            }
+        }
+    }
+
+    async fn post(&self, request: rpc::Request) -> web3::Result<Vec<rpc::Output>> {
+        let response = self
+            .0
+            .client
+            .post(self.0.url.clone())
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(helpers::to_string(&request))
+            .send()
+            .await
+            .map_err(|e| Error::Transport(format!("{:?}", e)))?;
+        let status = response.status();
+        let body = response
+            .bytes()
+            .await
+            .map_err(|e| Error::Transport(format!("{:?}", e)))?;
+        if !status.is_success() {
+            return Err(Error::Transport(format!(
+                "Unexpected status code {}: {}",
+                status,
+                String::from_utf8_lossy(&body)
+            )));
+        }
+        match helpers::to_response_from_slice(&body)? {
+            rpc::Response::Batch(outputs) => Ok(outputs),
+            rpc::Response::Single(output) => Ok(vec![output]),
+        }
+    }
+}
+
+impl fmt::Debug for BatchingHttp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BatchingHttp")
+            .field("url", &self.0.url)
+            .field("window", &self.0.window)
+            .finish()
+    }
+}
+
+impl Transport for BatchingHttp {
+    type Out = BoxFuture<'static, web3::Result<rpc::Value>>;
+
+    fn prepare(&self, method: &str, params: Vec<rpc::Value>) -> (RequestId, rpc::Call) {
+        let id = self.0.id.fetch_add(1, Ordering::AcqRel);
+        (id, helpers::build_request(id, method, params))
+    }
+
+    fn send(&self, id: RequestId, request: rpc::Call) -> Self::Out {
+        let (reply, response) = oneshot::channel();
+        let (queued, batch) = {
+            let mut queue = self.0.queue.lock().unwrap();
+            queue.pending.push(Pending { id, request, reply });
+            (queue.pending.len(), queue.batch)
+        };
+
+        if queued >= MAX_BATCH_SIZE {
+            self.flush();
+        } else if queued == 1 {
+            // The first request of a batch starts the window
+            let this = self.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(this.0.window).await;
+                this.flush_window(batch);
+            });
+        }
+
+        Box::pin(async move {
+            response
+                .await
+                .unwrap_or_else(|_| Err(Error::Transport("The batch was dropped".to_owned())))
+        })
+    }
+}
+
+/// A WebSocket transport which reconnects when the connection is lost. Rather
+/// than failing, a request which finds the connection gone is retried once
+/// on a new connection. The exception is requests sending transactions, as
+/// the node may have received the transaction before the connection was
+/// lost. These fail, and the next request reconnects.
+///
+/// Subscriptions do not survive a reconnection. Their streams end when the
+/// connection is lost, and the subscriber should subscribe again.
+///
+/// This must be used within a tokio runtime.
+#[derive(Clone)]
+pub struct ReconnectingWebSocket(Arc<ReconnectingWebSocketInner>);
+
+struct ReconnectingWebSocketInner {
+    url: String,
+    id: AtomicUsize,
+    /// The current connection, and how many times it has been replaced
+    socket: Mutex<(usize, WebSocket)>,
+    reconnecting: tokio::sync::Mutex<()>,
+}
+
+impl ReconnectingWebSocket {
+    pub async fn new(url: &str) -> web3::Result<Self> {
+        let socket = WebSocket::new(url).await?;
+        let inner = ReconnectingWebSocketInner {
+            url: url.to_owned(),
+            id: AtomicUsize::new(1),
+            socket: Mutex::new((0, socket)),
+            reconnecting: tokio::sync::Mutex::new(()),
+        };
+        Ok(Self(Arc::new(inner)))
+    }
+
+    fn socket(&self) -> (usize, WebSocket) {
+        self.0.socket.lock().unwrap().clone()
+    }
+
+    /// Replaces the connection, unless another request already replaced it
+    /// after it failed for this one.
+    async fn reconnect(&self, failed: usize) -> web3::Result<()> {
+        let _reconnecting = self.0.reconnecting.lock().await;
+        if self.socket().0 != failed {
+            return Ok(());
+        }
+        let socket = WebSocket::new(&self.0.url).await?;
+        *self.0.socket.lock().unwrap() = (failed + 1, socket);
+        Ok(())
+    }
+}
+
+impl fmt::Debug for ReconnectingWebSocket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReconnectingWebSocket")
+            .field("url", &self.0.url)
+            .finish()
+    }
+}
+
+impl Transport for ReconnectingWebSocket {
+    type Out = BoxFuture<'static, web3::Result<rpc::Value>>;
+
+    fn prepare(&self, method: &str, params: Vec<rpc::Value>) -> (RequestId, rpc::Call) {
+        let id = self.0.id.fetch_add(1, Ordering::AcqRel);
+        (id, helpers::build_request(id, method, params))
+    }
+
+    fn send(&self, id: RequestId, request: rpc::Call) -> Self::Out {
+        let this = self.clone();
+        Box::pin(async move {
+            let (generation, socket) = this.socket();
+            // Transport errors from the WebSocket all mean the connection
+            // has gone, whether before or while the request was made.
+            match Transport::send(&socket, id, request.clone()).await {
+                Err(Error::Transport(_)) if !sends_transaction(method(&request)) => {
+                    this.reconnect(generation).await?;
+                    Transport::send(&this.socket().1, id, request).await
+                }
+                Err(Error::Transport(e)) => {
+                    let _ignore = this.reconnect(generation).await;
+                    Err(Error::Transport(e))
+                }
+                result => result,
+            }
+        })
+    }
+}
+
+impl DuplexTransport for ReconnectingWebSocket {
+    type NotificationStream = <WebSocket as DuplexTransport>::NotificationStream;
+
+    fn subscribe(&self, id: SubscriptionId) -> web3::Result<Self::NotificationStream> {
+        self.socket().1.subscribe(id)
+    }
+
+    fn unsubscribe(&self, id: SubscriptionId) -> web3::Result<()> {
+        self.socket().1.unsubscribe(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::secret_key;
+    use crate::test_support::serve_http;
+    use futures::StreamExt as _;
+    use serde_json::{json, Value};
+    use tokio::net::TcpListener;
+    use tokio_util::compat::TokioAsyncReadCompatExt as _;
+    use web3::types::{Address, H256};
+
+    /// Serves a single HTTP request, answering a JSON-RPC batch by echoing
+    /// each request's params in reverse order.
+    async fn serve_reversed(listener: &TcpListener) -> usize {
+        let mut count = 0;
+        serve_http(listener, |requests| {
+            let requests = requests.as_array().unwrap();
+            count = requests.len();
+            requests
+                .iter()
+                .rev()
+                .map(|r| json!({"jsonrpc": "2.0", "id": r["id"], "result": r["params"][0]}))
+                .collect()
+        })
+        .await;
+        count
+    }
+
+    #[tokio::test]
+    pub async fn batches_and_demultiplexes() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move { serve_reversed(&listener).await });
+
+        let transport = BatchingHttp::new(&url, Duration::from_millis(50)).unwrap();
+        let (a, b, c) = futures::join!(
+            transport.execute("echo", vec![json!("a")]),
+            transport.execute("echo", vec![json!("b")]),
+            transport.execute("echo", vec![json!("c")]),
+        );
+
+        assert_eq!(server.await.unwrap(), 3);
+        assert_eq!(a.unwrap(), json!("a"));
+        assert_eq!(b.unwrap(), json!("b"));
+        assert_eq!(c.unwrap(), json!("c"));
+    }
+
+    /// A batch flushed early doesn't cut short the window of the next
+    #[tokio::test]
+    pub async fn windows_each_batch() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            serve_reversed(&listener).await;
+            serve_reversed(&listener).await;
+        });
+
+        let window = Duration::from_millis(400);
+        let transport = BatchingHttp::new(&url, window).unwrap();
+        let first = transport.execute("echo", vec![json!("a")]);
+        transport.flush();
+        assert_eq!(first.await.unwrap(), json!("a"));
+
+        // Made within the window of the first request, which was flushed
+        tokio::time::sleep(window / 4).await;
+        let started = tokio::time::Instant::now();
+        let second = transport.execute("echo", vec![json!("b")]).await;
+        assert_eq!(second.unwrap(), json!("b"));
+        assert!(started.elapsed() >= window);
+        server.await.unwrap();
+    }
+
+    /// Serves WebSocket connections, answering `n` requests on each (with
+    /// the number of the connection) before closing it.
+    async fn serve_ws(listener: TcpListener, n: usize) {
+        let mut connection = 0;
+        loop {
+            let (socket, _) = listener.accept().await.unwrap();
+            connection += 1;
+            let mut server = soketto::handshake::Server::new(socket.compat());
+            let key = server.receive_request().await.unwrap().into_key();
+            let accept = soketto::handshake::server::Response::Accept {
+                key: &key,
+                protocol: None,
+            };
+            server.send_response(&accept).await.unwrap();
+            let (mut sender, mut receiver) = server.into_builder().finish();
+            for _ in 0..n {
+                let mut data = Vec::new();
+                receiver.receive_data(&mut data).await.unwrap();
+                let request: Value = serde_json::from_slice(&data).unwrap();
+                let response = json!({"jsonrpc": "2.0", "id": request["id"], "result": connection});
+                sender.send_text(response.to_string()).await.unwrap();
+                sender.flush().await.unwrap();
+            }
+            sender.close().await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    pub async fn reconnects_websocket() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        tokio::spawn(serve_ws(listener, 1));
+
+        let transport = ReconnectingWebSocket::new(&url).await.unwrap();
+        let first = transport.execute("eth_blockNumber", vec![]).await;
+        assert_eq!(first.unwrap(), json!(1));
+        let second = transport.execute("eth_blockNumber", vec![]).await;
+        assert_eq!(second.unwrap(), json!(2));
+    }
+
+    /// Serves a WebSocket connection which answers eth_subscribe, and then
+    /// notifies the subscriber of a pending transaction when it next makes
+    /// a request. By then it is surely listening for notifications.
+    async fn serve_subscription(listener: TcpListener) {
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut server = soketto::handshake::Server::new(socket.compat());
+        let key = server.receive_request().await.unwrap().into_key();
+        let accept = soketto::handshake::server::Response::Accept {
+            key: &key,
+            protocol: None,
+        };
+        server.send_response(&accept).await.unwrap();
+        let (mut sender, mut receiver) = server.into_builder().finish();
+        let mut data = Vec::new();
+        receiver.receive_data(&mut data).await.unwrap();
+        let request: Value = serde_json::from_slice(&data).unwrap();
+        assert_eq!(request["method"], "eth_subscribe");
+        assert_eq!(request["params"], json!(["newPendingTransactions"]));
+        let response = json!({"jsonrpc": "2.0", "id": request["id"], "result": "0x1"});
+        sender.send_text(response.to_string()).await.unwrap();
+        sender.flush().await.unwrap();
+
+        data.clear();
+        receiver.receive_data(&mut data).await.unwrap();
+        let request: Value = serde_json::from_slice(&data).unwrap();
+        let response = json!({"jsonrpc": "2.0", "id": request["id"], "result": "0x0"});
+        sender.send_text(response.to_string()).await.unwrap();
+        let notification = json!({
+            "jsonrpc": "2.0",
+            "method": "eth_subscription",
+            "params": {"subscription": "0x1", "result": H256::repeat_byte(7)}
+        });
+        sender.send_text(notification.to_string()).await.unwrap();
+        sender.flush().await.unwrap();
+        // Keep the connection open until the subscriber is done
+        data.clear();
+        let _ignore = receiver.receive_data(&mut data).await;
+    }
+
+    #[tokio::test]
+    pub async fn subscribes_over_websocket() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        tokio::spawn(serve_subscription(listener));
+
+        let secret_key = secret_key();
+        let context = crate::Web3Context::ws(&url, Address::zero(), &secret_key)
+            .await
+            .unwrap();
+        let mut pending = context
+            .eth_subscribe()
+            .unwrap()
+            .subscribe_new_pending_transactions()
+            .await
+            .unwrap();
+        context.eth().block_number().await.unwrap();
+        let hash = pending.next().await.unwrap().unwrap();
+        assert_eq!(hash, H256::repeat_byte(7));
+
+        let transport = BatchingHttp::new("http://localhost", Duration::from_millis(1)).unwrap();
+        let context = crate::Web3Context::with_transport(transport, Address::zero(), &secret_key);
+        assert!(context.eth_subscribe().is_none());
+    }
+}
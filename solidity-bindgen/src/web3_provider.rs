@@ -1,17 +1,24 @@
 use crate::context::Web3Context;
 use crate::providers::{CallProvider, SendProvider};
+use crate::transaction::{Fees, Transaction};
+use crate::{
+    AccessListReport, CallOptions, FeeEstimator, FeeModel, PendingTransaction, SendOptions,
+    SentTransaction,
+};
 use async_trait::async_trait;
 use std::marker::Unpin;
+use std::sync::Arc;
+use web3::api::Namespace as _;
 use web3::contract::tokens::{Detokenize, Tokenize};
-use web3::contract::Contract;
-use web3::contract::Options;
-use web3::transports::Http;
-use web3::types::{Address, TransactionReceipt};
+use web3::helpers;
+use web3::types::{Address, Bytes, CallRequest};
+use web3::Transport as _;
 
 /// Mostly exists to map to the new futures.
 /// This is the "untyped" API which the generated types will use.
 pub struct Web3Provider {
-    contract: Contract<Http>,
+    address: Address,
+    abi: Arc<ethabi::Contract>,
     context: Web3Context,
 }
 
@@ -21,80 +28,345 @@ impl CallProvider for Web3Provider {
         &self,
         name: &'static str,
         params: Params,
+        options: CallOptions,
     ) -> Result<O, web3::Error> {
-        match self
-            .contract
-            .query(
-                name,
-                params,
-                Some(self.context.from()),
-                Default::default(),
-                None,
+        // Errors from the ABI should be prevented by the code gen. It is
+        // useful to restrict the error type to web3::Error for a few reasons.
+        // First, the web3::Error type (unlike the web3::contract::Error type)
+        // implements Send. This makes it usable in async methods. Also for
+        // consistency it's easier to mix methods using both call and send to
+        // use the ? operator if they have the same error type. It is the
+        // opinion of this library that ABI sorts of errors are irrecoverable
+        // and should panic anyway.
+        let abi_out_of_date = |e: &dyn std::fmt::Display| -> ! {
+            panic!("The ABI is out of date. Name: {}. Inner: {}", name, e)
+        };
+
+        let function = self
+            .abi
+            .function(name)
+            .unwrap_or_else(|e| abi_out_of_date(&e));
+        let data = function
+            .encode_input(&params.into_tokens())
+            .unwrap_or_else(|e| abi_out_of_date(&e));
+
+        let request = CallRequest {
+            from: Some(options.from.unwrap_or_else(|| self.context.from())),
+            to: Some(self.address),
+            gas: options.gas,
+            gas_price: options.gas_price,
+            value: options.value,
+            data: Some(Bytes(data)),
+            ..Default::default()
+        };
+
+        // web3's Eth::call only accepts a BlockId, which can't express all of
+        // the blocks in BlockRef. So, the request is made directly.
+        let eth = self.context.eth();
+        let result = eth
+            .transport()
+            .execute(
+                "eth_call",
+                vec![
+                    helpers::serialize(&request),
+                    helpers::serialize(&options.block),
+                ],
             )
-            .await
-        {
-            Ok(v) => Ok(v),
-            Err(e) => match e {
-                web3::contract::Error::Api(e) => Err(e),
-                // The other variants InvalidOutputType and Abi should be
-                // prevented by the code gen. It is useful to convert the error
-                // type to be restricted to the web3::Error type for a few
-                // reasons. First, the web3::Error type (unlike the
-                // web3::contract::Error type) implements Send. This makes it
-                // usable in async methods. Also for consistency it's easier to
-                // mix methods using both call and send to use the ? operator if
-                // they have the same error type. It is the opinion of this
-                // library that ABI sorts of errors are irrecoverable and should
-                // panic anyway.
-                e => panic!("The ABI is out of date. Name: {}. Inner: {}", name, e),
-            },
+            .await?;
+        let result: Bytes =
+            serde_json::from_value(result).map_err(|e| web3::Error::Decoder(format!("{:?}", e)))?;
+        // There is no code at the address, eg: at a block before the
+        // contract was deployed. This isn't a problem with the ABI.
+        if result.0.is_empty() && !function.outputs.is_empty() {
+            return Err(web3::Error::InvalidResponse(format!(
+                "{} returned no data. There may be no contract at {:?} at block {:?}",
+                name, self.address, options.block
+            )));
         }
+
+        let tokens = function
+            .decode_output(&result.0)
+            .unwrap_or_else(|e| abi_out_of_date(&e));
+        O::from_tokens(tokens).map_err(|e| abi_out_of_date(&e))
     }
 }
 
 #[async_trait]
 impl SendProvider for Web3Provider {
-    type Out = TransactionReceipt;
+    type Out = PendingTransaction;
     async fn send<Params: Tokenize + Send>(
         &self,
         func: &'static str,
         params: Params,
-        options: Option<Options>,
+        options: Option<SendOptions>,
         confirmations: Option<usize>,
     ) -> Result<Self::Out, web3::Error> {
-        self.contract
-            .signed_call_with_confirmations(
-                func,
-                params,
-                match options {
-                    None => Default::default(),
-                    Some(options) => options,
-                },
-                match confirmations {
-                    // Num confirmations. From a library standpoint, this should be
-                    // a parameter of the function. Choosing a correct value is very
-                    // difficult, even for a consumer of the library as it would
-                    // require assessing the value of the transaction, security
-                    // margins, and a number of other factors for which data may not
-                    // be available. So just picking a pretty high security margin
-                    // for now.
-                    None => 24,
-                    Some(confirmations) => confirmations,
-                },
-                self.context.secret_key(),
-            )
-            .await
+        let data = self
+            .abi
+            .function(func)
+            .and_then(|function| function.encode_input(&params.into_tokens()))
+            .map_err(|err| web3::Error::Decoder(format!("{:?}", err)))?;
+        self.sign_and_send(data, options, confirmations).await
+    }
+
+    async fn send_data(
+        &self,
+        data: Vec<u8>,
+        options: Option<SendOptions>,
+        confirmations: Option<usize>,
+    ) -> Result<Self::Out, web3::Error> {
+        self.sign_and_send(data, options, confirmations).await
     }
 }
 
 impl Web3Provider {
-    pub fn new(contract_address: Address, context: &Web3Context, json_abi: &[u8]) -> Self {
-        let context = context.clone();
+    pub fn new(
+        contract_address: Address,
+        context: &Web3Context,
+        abi: Arc<ethabi::Contract>,
+    ) -> Self {
+        Self {
+            address: contract_address,
+            abi,
+            context: context.clone(),
+        }
+    }
+
+    /// The signing path shared by all sends, whether the data is for a
+    /// function in the ABI or not.
+    async fn sign_and_send(
+        &self,
+        data: Vec<u8>,
+        options: Option<SendOptions>,
+        confirmations: Option<usize>,
+    ) -> Result<PendingTransaction, web3::Error> {
+        let options = options.unwrap_or_default();
+        let from = options.from.unwrap_or_else(|| self.context.from());
+        let signer = self.context.signer_of(from)?;
+        let nonces = self.context.nonces();
+        let eth = self.context.eth();
+        let chain_id = eth.chain_id().await?.as_u64();
+        let fees = self.fees(&options).await?;
+        let access_list = match (&options.access_list, options.create_access_list) {
+            (Some(access_list), _) => Some(access_list.clone()),
+            (None, true) => {
+                let report = self.create_access_list(data.clone(), &options).await?;
+                log::info!(
+                    "Access list for {:?} changes estimated gas from {} to {}",
+                    self.address,
+                    report.gas_without,
+                    report.gas_with
+                );
+                if report.reduces_gas() {
+                    Some(report.access_list)
+                } else {
+                    None
+                }
+            }
+            (None, false) => None,
+        };
+
+        let mut resynced = false;
+        let (hash, transaction) = loop {
+            // Nonces from the options are the caller's to manage
+            let nonce = match options.nonce {
+                Some(nonce) => nonce,
+                None => nonces.next(&eth, from).await?,
+            };
+            let tx = Transaction {
+                chain_id,
+                nonce,
+                to: Some(self.address),
+                // The default of web3, which this used to sign with
+                gas: options.gas.unwrap_or_else(|| 100_000.into()),
+                fees: fees.clone(),
+                value: options.value.unwrap_or_default(),
+                data: Bytes(data.clone()),
+                access_list: access_list.clone(),
+            };
+
+            let signed = match signer.sign_transaction(&tx).await {
+                Ok(signed) => signed,
+                Err(err) => {
+                    if options.nonce.is_none() {
+                        nonces.release(from, nonce).await;
+                    }
+                    return Err(err);
+                }
+            };
+            let err = match eth.send_raw_transaction(Bytes(signed.raw)).await {
+                Ok(hash) => break (hash, tx),
+                Err(err) if options.nonce.is_some() => return Err(err),
+                Err(err) => err,
+            };
+
+            match NonceError::of(&err) {
+                // Something else has sent from the account. Try again once
+                // with the nonce the node expects.
+                Some(NonceError::TooLow) if !resynced => {
+                    nonces.resync(from).await;
+                    resynced = true;
+                }
+                // This exact transaction was broadcast already, eg: by an
+                // earlier attempt that appeared to fail.
+                Some(NonceError::AlreadyKnown) => {
+                    nonces.resync(from).await;
+                    break (signed.hash, tx);
+                }
+                Some(NonceError::TooLow) => {
+                    nonces.resync(from).await;
+                    return Err(err);
+                }
+                // The node rejected the transaction, so the nonce is unused
+                None if matches!(err, web3::Error::Rpc(_)) => {
+                    nonces.release(from, nonce).await;
+                    return Err(err);
+                }
+                // The transaction may or may not have been broadcast. Only
+                // the node knows whether the nonce was used.
+                None => {
+                    nonces.resync(from).await;
+                    return Err(err);
+                }
+            }
+        };
+
+        let sent = SentTransaction {
+            hash,
+            from,
+            nonce: transaction.nonce,
+            confirmations: confirmations.unwrap_or_else(|| self.context.confirmations()),
+            replaces: Vec::new(),
+            transaction,
+        };
+        Ok(PendingTransaction::new(self.context.clone(), sent))
+    }
+
+    /// Asks the node for the access list of a send, and whether it would
+    /// reduce the gas used. Sends do this themselves when given the
+    /// `create_access_list` option.
+    pub async fn create_access_list(
+        &self,
+        data: Vec<u8>,
+        options: &SendOptions,
+    ) -> Result<AccessListReport, web3::Error> {
+        let request = CallRequest {
+            from: Some(options.from.unwrap_or_else(|| self.context.from())),
+            to: Some(self.address),
+            value: options.value,
+            data: Some(Bytes(data)),
+            ..Default::default()
+        };
+        AccessListReport::create(&self.context.eth(), request).await
+    }
+
+    /// The fees for a send. Fees in the options decide the type of the
+    /// transaction, and otherwise the FeeModel of the context does.
+    async fn fees(&self, options: &SendOptions) -> Result<Fees, web3::Error> {
+        let eth = self.context.eth();
+        if options.max_fee_per_gas.is_none() && options.max_priority_fee_per_gas.is_none() {
+            match (options.gas_price, self.context.fee_model()) {
+                (Some(gas_price), _) => return Ok(Fees::Legacy { gas_price }),
+                (None, FeeModel::Legacy) => {
+                    let gas_price = eth.gas_price().await?;
+                    return Ok(Fees::Legacy { gas_price });
+                }
+                (None, FeeModel::Eip1559(_)) => {}
+            }
+        }
+        let estimator = match self.context.fee_model() {
+            FeeModel::Eip1559(estimator) => estimator.clone(),
+            // EIP-1559 fees were given to a context which sends legacy
+            // transactions
+            FeeModel::Legacy => FeeEstimator::default(),
+        };
 
-        // All of the ABIs are verified at compile time, so we can just unwrap here.
-        // See also 4cd1038f-56f2-4cf2-8dbe-672da9006083
-        let contract = Contract::from_json(context.eth(), contract_address, json_abi).unwrap();
+        let (max_fee_per_gas, max_priority_fee_per_gas) =
+            match (options.max_fee_per_gas, options.max_priority_fee_per_gas) {
+                (Some(max_fee), Some(priority_fee)) => (max_fee, priority_fee),
+                (max_fee, priority_fee) => {
+                    let (estimated_max_fee, estimated_priority_fee) =
+                        estimator.estimate(&eth).await?;
+                    let priority_fee = priority_fee.unwrap_or(estimated_priority_fee);
+                    // The max fee must cover the priority fee
+                    let max_fee = max_fee.unwrap_or_else(|| {
+                        estimated_max_fee - estimated_priority_fee + priority_fee
+                    });
+                    (max_fee, priority_fee.min(max_fee))
+                }
+            };
+        Ok(Fees::Eip1559 {
+            max_fee_per_gas,
+            max_priority_fee_per_gas,
+        })
+    }
+}
+
+/// Errors from sending a transaction which mean the nonce manager is out of
+/// sync with the node. There is no standard for these, so this matches the
+/// messages of geth and the nodes which copy it.
+enum NonceError {
+    TooLow,
+    AlreadyKnown,
+}
+
+impl NonceError {
+    fn of(err: &web3::Error) -> Option<Self> {
+        let message = match err {
+            web3::Error::Rpc(err) => err.message.to_lowercase(),
+            _ => return None,
+        };
+        if message.contains("nonce too low") || message.contains("nonce is too low") {
+            Some(Self::TooLow)
+        } else if message.contains("already known") || message.contains("known transaction") {
+            Some(Self::AlreadyKnown)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::Node;
+    use crate::transaction::SignedTransaction;
+    use crate::{Context as _, Signer};
+    use serde_json::json;
+    use web3::signing::Signature;
+
+    /// A signer which can't be reached, eg: a signing service which is down
+    struct Unreachable;
+
+    #[async_trait]
+    impl Signer for Unreachable {
+        fn address(&self) -> Address {
+            Address::repeat_byte(1)
+        }
+
+        async fn sign_transaction(&self, _: &Transaction) -> web3::Result<SignedTransaction> {
+            Err(web3::Error::Unreachable)
+        }
+
+        async fn sign_message(&self, _: &[u8]) -> web3::Result<Signature> {
+            Err(web3::Error::Unreachable)
+        }
+    }
 
-        Self { contract, context }
+    #[test]
+    pub fn releases_nonce_when_signing_fails() {
+        // Answers the requests made before signing
+        let node = Node::new(|method, _| match method {
+            "eth_getTransactionCount" => json!("0x5"),
+            _ => json!("0x1"),
+        });
+        let context = Web3Context::with_signer(node, Unreachable);
+        let provider = context.provider(Address::repeat_byte(2), Default::default());
+        futures::executor::block_on(async {
+            let result = provider.send_data(Vec::new(), None, None).await;
+            assert!(matches!(result, Err(web3::Error::Unreachable)));
+            let nonce = context.nonces().next(&context.eth(), context.from()).await;
+            assert_eq!(nonce.unwrap(), 5.into());
+        });
     }
 }
@@ -0,0 +1,139 @@
+use solidity_bindgen::{contract_abi, BlockRef, CallOptions, MockContext, Multicall, SendOptions};
+use web3::ethabi::{self, Token as AbiToken};
+use web3::types::{Address, U256};
+
+// Token.json has receive and fallback functions and NatSpec docs, so this
+// also checks that the docs generated from NatSpec compile
+contract_abi!("solidity-bindgen/tests/contracts/Token.json");
+
+#[test]
+fn sends_with_send_options() {
+    let mock = MockContext::new();
+    mock.expect("transfer").returns(());
+    let token = Token::new(Address::repeat_byte(1), &mock);
+    let to = Address::repeat_byte(2);
+
+    futures::executor::block_on(async {
+        token.transfer(to, 1.into()).await.unwrap();
+        let options = SendOptions::with(|o| o.create_access_list = true);
+        let view = token.with_send_options(options);
+        view.transfer(to, 2.into()).await.unwrap();
+        view.receive(3.into()).await.unwrap();
+    });
+
+    let sends = mock.sends();
+    assert_eq!(sends[0].options, None);
+    let options = sends[1].options.as_ref().unwrap();
+    assert!(options.create_access_list);
+    assert_eq!(
+        sends[1].params,
+        vec![AbiToken::Address(to), AbiToken::Uint(U256::from(2))]
+    );
+    let options = sends[2].options.as_ref().unwrap();
+    assert!(options.create_access_list);
+    assert_eq!(options.value, Some(3.into()));
+}
+
+#[test]
+fn sends_to_receive_and_fallback() {
+    let mock = MockContext::new();
+    let token = Token::new(Address::repeat_byte(1), &mock);
+
+    futures::executor::block_on(async {
+        token.receive(1.into()).await.unwrap();
+        token.fallback(vec![1, 2, 3], 2.into()).await.unwrap();
+    });
+
+    let sends = mock.sends();
+    assert_eq!(sends[0].function, None);
+    assert!(sends[0].data.is_empty());
+    assert_eq!(sends[0].options.as_ref().unwrap().value, Some(1.into()));
+    assert_eq!(sends[1].function, None);
+    assert_eq!(sends[1].data, vec![1, 2, 3]);
+    assert_eq!(sends[1].options.as_ref().unwrap().value, Some(2.into()));
+}
+
+#[test]
+fn calls_with_views() {
+    let mock = MockContext::new();
+    mock.expect("balanceOf").returns(U256::from(7));
+    let token = Token::new(Address::repeat_byte(1), &mock);
+    let owner = Address::repeat_byte(2);
+    let account = Address::repeat_byte(3);
+    let caller = Address::repeat_byte(4);
+
+    futures::executor::block_on(async {
+        assert_eq!(token.balance_of(owner).await.unwrap(), U256::from(7));
+        token.at_block(5).balance_of(owner).await.unwrap();
+        let view = token.with_account(account);
+        view.balance_of(owner).await.unwrap();
+        let options = CallOptions {
+            gas: Some(100_000.into()),
+            ..Default::default()
+        };
+        // The options have no from, so calls are still made as the account
+        view.with_call_options(options.clone())
+            .balance_of(owner)
+            .await
+            .unwrap();
+        let options = CallOptions {
+            from: Some(caller),
+            ..options
+        };
+        view.with_call_options(options)
+            .balance_of(owner)
+            .await
+            .unwrap();
+    });
+
+    let calls = mock.calls();
+    assert_eq!(calls[0].params, vec![AbiToken::Address(owner)]);
+    assert_eq!(calls[0].options.block, BlockRef::Latest);
+    assert_eq!(calls[0].options.from, None);
+    assert_eq!(calls[1].options.block, BlockRef::from(5));
+    assert_eq!(calls[2].options.from, Some(account));
+    assert_eq!(calls[3].options.from, Some(account));
+    assert_eq!(calls[3].options.gas, Some(100_000.into()));
+    assert_eq!(calls[4].options.from, Some(caller));
+}
+
+#[test]
+fn sends_from_account() {
+    let mock = MockContext::new();
+    mock.expect("transfer").returns(());
+    let token = Token::new(Address::repeat_byte(1), &mock);
+    let account = Address::repeat_byte(3);
+
+    futures::executor::block_on(async {
+        let view = token.with_account(account);
+        view.transfer(Address::repeat_byte(2), 1.into())
+            .await
+            .unwrap();
+    });
+
+    let sends = mock.sends();
+    assert_eq!(sends[0].function.as_deref(), Some("transfer"));
+    assert_eq!(sends[0].options.as_ref().unwrap().from, Some(account));
+}
+
+#[test]
+fn prepares_calls_for_multicall() {
+    let mock = MockContext::new();
+    let owner = Address::repeat_byte(2);
+    let balance = ethabi::encode(&[AbiToken::Uint(U256::from(7))]);
+    mock.expect("aggregate3")
+        .returns(AbiToken::Array(vec![AbiToken::Tuple(vec![
+            AbiToken::Bool(true),
+            AbiToken::Bytes(balance),
+        ])]));
+    let token = Token::new(Address::repeat_byte(1), &mock);
+
+    let mut multicall = Multicall::new(&mock);
+    let handle = multicall.add(token.prepare_balance_of(owner));
+    let results = futures::executor::block_on(multicall.call()).unwrap();
+    assert_eq!(results.get(&handle).unwrap(), U256::from(7));
+
+    let calls = mock.calls();
+    assert_eq!(calls.len(), 1);
+    assert_eq!(calls[0].function, "aggregate3");
+}